@@ -0,0 +1,95 @@
+//! Throughput of the html and css parsers on generated multi-megabyte input.
+//! Run with `cargo bench`.
+
+extern crate rbe;
+
+use std::hint::black_box;
+use std::time::{ Duration, Instant };
+use rbe::{ html, parser, style };
+use rbe::encoding::Encoding;
+use rbe::tokenizer::{ Tokenizer, Token };
+
+const RUNS: usize = 5;
+
+/// A document of about `size` bytes with the usual mix of markup, text, attributes and
+/// character references. It declares its encoding, so that `parse_bytes` decodes it as UTF-8.
+fn document (size: usize) -> String {
+    let mut source = String::from("<!DOCTYPE html><html><head><meta charset=utf-8><title>Benchmark</title></head><body>\n");
+    let mut i = 0;
+    while source.len() < size {
+        source.push_str(&format!(
+            "<div class=\"item item-{} odd\" id=\"item{}\" data-index={}>\n\
+             <h2>Heading &amp; more</h2>\n\
+             <p>Lorem ipsum dolor sit amet, <b>consectetur</b> adipiscing elit, sed do <a href=\"/page?a=1&amp;b={}\">eiusmod</a> tempor.\n\
+             <ul><li>caf\u{e9}<li>na\u{ef}ve &lt;tag&gt;<li>&#x1F600; &copy;</ul>\n\
+             <table><tr><td>1<td>2</tr><tr><td colspan=2>3</table>\n\
+             <!-- item {} -->\n\
+             <input type=checkbox checked><br><img src=\"a.png\" alt=\"\">\n\
+             </div>\n",
+            i % 7, i, i, i, i));
+        i += 1;
+    }
+    source.push_str("</body></html>\n");
+    source
+}
+
+/// A stylesheet of about `size` bytes
+fn stylesheet (size: usize) -> String {
+    let mut source = String::new();
+    let mut i = 0;
+    while source.len() < size {
+        source.push_str(&format!(
+            "div.item-{}, p#item{} {{ margin-top: {}px; color: #{:02x}{:02x}{:02x}; display: block; }}\n\
+             h2 {{ padding: 4px; white-space: nowrap; }}\n",
+            i % 7, i, i % 20, i % 256, (i * 3) % 256, (i * 7) % 256));
+        i += 1;
+    }
+    source
+}
+
+/// Run `f` a few times and report the throughput of the fastest run
+fn bench<F> (name: &str, bytes: usize, mut f: F) where F: FnMut() {
+    let mut best = Duration::from_secs(u64::MAX);
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    let megabytes = bytes as f64 / (1024.0 * 1024.0);
+    println!("{:<12} {:>6.1} MB in {:>8.2} ms  {:>8.1} MB/s",
+             name, megabytes, best.as_secs_f64() * 1000.0, megabytes / best.as_secs_f64());
+}
+
+fn main () {
+    let source = document(8 * 1024 * 1024);
+    let css = stylesheet(2 * 1024 * 1024);
+
+    bench("tokenize", source.len(), || {
+        let mut tokenizer = Tokenizer::from_source(&source);
+        while let Some((token, _)) = tokenizer.next_token() {
+            if token == Token::Eof {
+                break;
+            }
+            black_box(token);
+        }
+    });
+    bench("parse", source.len(), || {
+        black_box(html::parse(source.clone()));
+    });
+    let decoded = html::parse_bytes(source.as_bytes(), None);
+    assert_eq!(decoded.encoding, Encoding::Utf8);
+    assert!(decoded.source == source, "the corpus does not round-trip through parse_bytes");
+    bench("parse_bytes", source.len(), || {
+        black_box(html::parse_bytes(source.as_bytes(), None));
+    });
+    bench("css", css.len(), || {
+        black_box(parser::parse_stylesheet(&css));
+    });
+
+    let document = html::parse(source.clone()).document;
+    let root = document.document_element().unwrap();
+    let rules = parser::parse_stylesheet(&stylesheet(2 * 1024));
+    bench("style", source.len(), || {
+        black_box(style::style_tree(&document, root, &rules));
+    });
+}
@@ -0,0 +1,192 @@
+//! Names of elements, attributes and css properties.
+//!
+//! The names html and css are written with come from a fixed table, so atoms for them are
+//! cheap to create and clone and comparing two of them only compares pointers. Other names,
+//! like custom elements or `data-` attributes, are owned by the atoms made for them and freed
+//! with them, so documents with many made up names don't grow a global table.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{ Hash, Hasher };
+use std::ops::Deref;
+use std::ptr;
+use std::sync::Arc;
+
+/// The names known in advance, sorted by their bytes
+const STATIC_ATOMS: &[&str] = &[
+    "", "a", "abbr", "accept", "accept-charset", "accesskey", "action", "actuate", "address",
+    "align", "alt", "altGlyph", "altGlyphDef", "altGlyphItem", "altglyph", "altglyphdef",
+    "altglyphitem", "animateColor", "animateMotion", "animateTransform", "animatecolor",
+    "animatemotion", "animatetransform", "annotation-xml", "arcrole", "area", "article", "aside",
+    "async", "attributeName", "attributeType", "attributename", "attributetype", "audio",
+    "autocomplete", "autofocus", "b", "background", "base", "baseFrequency", "baseProfile",
+    "basefont", "basefrequency", "baseprofile", "bdi", "bdo", "bgsound", "big", "blink",
+    "blockquote", "body", "border", "border-bottom-width", "border-color", "border-left-width",
+    "border-right-width", "border-top-width", "border-width", "br", "button", "calcMode",
+    "calcmode", "canvas", "caption", "center", "charset", "checked", "circle", "cite", "class",
+    "clipPath", "clipPathUnits", "clippath", "clippathunits", "code", "col", "colgroup", "color",
+    "cols", "colspan", "content", "contenteditable", "controls", "coords", "crossorigin", "d",
+    "data", "datalist", "datetime", "dd", "defer", "definitionURL", "definitionurl", "defs", "del",
+    "desc", "details", "dfn", "dialog", "diffuseConstant", "diffuseconstant", "dir", "disabled",
+    "display", "div", "dl", "download", "draggable", "dt", "edgeMode", "edgemode", "ellipse", "em",
+    "embed", "encoding", "enctype", "face", "feBlend", "feColorMatrix", "feComponentTransfer",
+    "feComposite", "feConvolveMatrix", "feDiffuseLighting", "feDisplacementMap", "feDistantLight",
+    "feDropShadow", "feFlood", "feFuncA", "feFuncB", "feFuncG", "feFuncR", "feGaussianBlur",
+    "feImage", "feMerge", "feMergeNode", "feMorphology", "feOffset", "fePointLight",
+    "feSpecularLighting", "feSpotLight", "feTile", "feTurbulence", "feblend", "fecolormatrix",
+    "fecomponenttransfer", "fecomposite", "feconvolvematrix", "fediffuselighting",
+    "fedisplacementmap", "fedistantlight", "fedropshadow", "feflood", "fefunca", "fefuncb",
+    "fefuncg", "fefuncr", "fegaussianblur", "feimage", "femerge", "femergenode", "femorphology",
+    "feoffset", "fepointlight", "fespecularlighting", "fespotlight", "fetile", "feturbulence",
+    "fieldset", "figcaption", "figure", "fill", "filterUnits", "filterunits", "font", "footer",
+    "for", "foreignObject", "foreignobject", "form", "frame", "frameset", "g", "glyphRef",
+    "glyphref", "gradientTransform", "gradientUnits", "gradienttransform", "gradientunits", "h1",
+    "h2", "h3", "h4", "h5", "h6", "head", "header", "height", "hgroup", "hidden", "hr", "href",
+    "hreflang", "html", "http-equiv", "http://www.w3.org/1998/Math/MathML",
+    "http://www.w3.org/1999/xhtml", "http://www.w3.org/1999/xlink", "http://www.w3.org/2000/svg",
+    "http://www.w3.org/2000/xmlns/", "http://www.w3.org/XML/1998/namespace", "i", "id", "iframe",
+    "image", "img", "input", "ins", "isindex", "kbd", "kernelMatrix", "kernelUnitLength",
+    "kernelmatrix", "kernelunitlength", "keyPoints", "keySplines", "keyTimes", "keygen",
+    "keypoints", "keysplines", "keytimes", "label", "lang", "language", "legend", "lengthAdjust",
+    "lengthadjust", "li", "limitingConeAngle", "limitingconeangle", "line", "linearGradient",
+    "lineargradient", "link", "list", "listing", "loop", "main", "malignmark", "map", "margin",
+    "margin-bottom", "margin-left", "margin-right", "margin-top", "mark", "markerHeight",
+    "markerUnits", "markerWidth", "markerheight", "markerunits", "markerwidth", "marquee",
+    "maskContentUnits", "maskUnits", "maskcontentunits", "maskunits", "math", "max", "maxlength",
+    "media", "menu", "menuitem", "meta", "meter", "method", "mglyph", "mi", "min", "mn", "mo", "ms",
+    "mtext", "multiple", "name", "nav", "nobr", "noembed", "noframes", "nonce", "noscript",
+    "novalidate", "numOctaves", "numoctaves", "object", "ol", "onclick", "onload", "optgroup",
+    "option", "output", "p", "padding", "padding-bottom", "padding-left", "padding-right",
+    "padding-top", "param", "path", "pathLength", "pathlength", "pattern", "patternContentUnits",
+    "patternTransform", "patternUnits", "patterncontentunits", "patterntransform", "patternunits",
+    "picture", "placeholder", "plaintext", "points", "pointsAtX", "pointsAtY", "pointsAtZ",
+    "pointsatx", "pointsaty", "pointsatz", "polygon", "polyline", "pre", "preserveAlpha",
+    "preserveAspectRatio", "preservealpha", "preserveaspectratio", "primitiveUnits",
+    "primitiveunits", "progress", "q", "radialGradient", "radialgradient", "rb", "readonly", "rect",
+    "refX", "refY", "refx", "refy", "rel", "repeatCount", "repeatDur", "repeatcount", "repeatdur",
+    "required", "requiredExtensions", "requiredFeatures", "requiredextensions", "requiredfeatures",
+    "role", "rows", "rowspan", "rp", "rt", "rtc", "ruby", "s", "samp", "sandbox", "scope", "script",
+    "search", "section", "select", "selected", "shape", "show", "size", "sizes", "slot", "small",
+    "source", "space", "span", "specularConstant", "specularExponent", "specularconstant",
+    "specularexponent", "spellcheck", "spreadMethod", "spreadmethod", "src", "srcdoc", "srclang",
+    "srcset", "start", "startOffset", "startoffset", "stdDeviation", "stddeviation", "step",
+    "stitchTiles", "stitchtiles", "stop", "strike", "stroke", "strong", "style", "sub", "summary",
+    "sup", "surfaceScale", "surfacescale", "svg", "symbol", "systemLanguage", "systemlanguage",
+    "tabindex", "table", "tableValues", "tablevalues", "target", "targetX", "targetY", "targetx",
+    "targety", "tbody", "td", "template", "text", "textLength", "textPath", "textarea",
+    "textlength", "textpath", "tfoot", "th", "thead", "time", "title", "tr", "track", "transform",
+    "translate", "tspan", "tt", "type", "u", "ul", "use", "usemap", "value", "var", "video",
+    "viewBox", "viewTarget", "viewbox", "viewtarget", "wbr", "white-space", "width", "wrap", "x",
+    "xChannelSelector", "xchannelselector", "xlink", "xml", "xmlns", "xmp", "y", "yChannelSelector",
+    "ychannelselector", "zoomAndPan", "zoomandpan"
+];
+
+#[derive(Clone)]
+pub struct Atom(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    /// An entry of `STATIC_ATOMS`
+    Static(&'static str),
+    /// A name that is not in the table
+    Owned(Arc<str>)
+}
+
+impl Atom {
+    /// The atom for a name, which only allocates for names that are not in the table
+    pub fn new (name: &str) -> Atom {
+        match STATIC_ATOMS.binary_search(&name) {
+            Ok(index) => Atom(Repr::Static(STATIC_ATOMS[index])),
+            Err(_) => Atom(Repr::Owned(Arc::from(name)))
+        }
+    }
+
+    pub fn as_str (&self) -> &str {
+        match self.0 {
+            Repr::Static(name) => name,
+            Repr::Owned(ref name) => name
+        }
+    }
+}
+
+impl Deref for Atom {
+    type Target = str;
+
+    fn deref (&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for Atom {
+    fn borrow (&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Atom {
+    fn from (name: &str) -> Atom {
+        Atom::new(name)
+    }
+}
+
+impl From<String> for Atom {
+    fn from (name: String) -> Atom {
+        Atom::new(&name)
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq (&self, other: &Atom) -> bool {
+        // A name in the table always gets the entry of the table, never an owned string
+        match (&self.0, &other.0) {
+            (Repr::Static(a), Repr::Static(b)) => ptr::eq(*a, *b),
+            (Repr::Owned(a), Repr::Owned(b)) => a == b,
+            _ => false
+        }
+    }
+}
+
+impl Eq for Atom {}
+
+impl PartialEq<str> for Atom {
+    fn eq (&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Atom {
+    fn eq (&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for Atom {
+    // Hashed like the string, so that maps keyed by atoms can be searched with a `&str`
+    fn hash<H: Hasher> (&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl PartialOrd for Atom {
+    fn partial_cmp (&self, other: &Atom) -> Option<::std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Atom {
+    fn cmp (&self, other: &Atom) -> ::std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl fmt::Debug for Atom {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
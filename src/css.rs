@@ -1,3 +1,5 @@
+use atom::Atom;
+
 #[derive(Debug)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>
@@ -16,14 +18,17 @@ pub enum Selector {
 
 #[derive(Debug)]
 pub struct SimpleSelector {
-    pub tag_name: Option<String>,
+    /// The url of the namespace matching elements are in, from a prefix like `svg|rect` or
+    /// the default `@namespace`. `None` matches any namespace.
+    pub namespace: Option<Atom>,
+    pub tag_name: Option<Atom>,
     pub id: Option<String>,
     pub class: Vec<String>
 }
 
 #[derive(Debug)]
 pub struct Declaration {
-    pub name: String,
+    pub name: Atom,
     pub value: Value
 }
 
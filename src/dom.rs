@@ -1,51 +1,1154 @@
 use std::collections::{ HashMap, HashSet };
+use std::fmt;
+use std::mem;
+use std::ops::Index;
+use atom::Atom;
 
-pub type AttrMap = HashMap<String, String>;
+/// The attributes of a start tag, in the order they were written
+pub type AttrMap = Vec<(Atom, String)>;
 
-#[derive(Debug)]
+/// A handle to a node of a `Document`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+/// The nodes of a document, which refer to each other by `NodeId`. Nodes that are removed
+/// from the tree stay in the document, detached, until it is dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    nodes: Vec<Node>,
+    url: Option<String>,
+    quirks_mode: QuirksMode,
+    /// The elements in the tree with each id, kept up to date as the tree and the attributes
+    /// change. Several elements can have the same id.
+    ids: HashMap<String, Vec<NodeId>>,
+    /// The changes not yet taken by each observer, `None` once it is disconnected
+    observers: Vec<Option<Vec<MutationRecord>>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Node {
     // data common to all nodes:
-    pub children: Vec<Node>,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    /// Whether the node is in the tree of the document
+    connected: bool,
 
     // data specific to each node type:
     pub node_type: NodeType,
+
+    // where the node was found in the parsed source, if it came from there:
+    pub span: Option<Span>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeType {
+    /// The root of a document, whose children are the doctype, the root element and the
+    /// comments around them
+    Document,
+    /// The `<!DOCTYPE>`, which can only be a child of the document and comes before its element
+    Doctype(Doctype),
+    /// The root of a tree of nodes outside of the document, like the contents of a template
+    DocumentFragment,
     Text(String),
     Element(ElementData),
+    Comment(String),
+}
+
+/// The children of a node, first to last
+pub struct Children<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+/// The nodes below a node in document order, each before its children
+pub struct Descendants<'a> {
+    document: &'a Document,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+/// The nodes below a node with each after its children, the order in which they are closed
+pub struct DescendantsPostOrder<'a> {
+    document: &'a Document,
+    root: NodeId,
+    next: Option<NodeId>,
 }
 
-#[derive(Debug)]
+/// The parent of a node, its parent and so on up to the root
+pub struct Ancestors<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+/// The nodes of another iterator that are elements
+pub struct Elements<'a, I> {
+    document: &'a Document,
+    nodes: I,
+}
+
+/// The attributes of an element, in the order they were added
+pub struct Attributes<'a> {
+    attributes: ::std::slice::Iter<'a, Attribute>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ElementData {
-    pub tag_name: String,
-    attributes: AttrMap,
+    pub name: QualName,
+    /// In source order, new attributes are added at the end
+    attributes: Vec<Attribute>,
+    template_contents: Option<NodeId>,
+}
+
+/// The name of an element or attribute together with its namespace, like `xlink:href` which
+/// has the `xlink` prefix, the `http://www.w3.org/1999/xlink` namespace and the local name
+/// `href`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QualName {
+    pub prefix: Option<Atom>,
+    /// The url of the namespace, empty for names without one like most attribute names
+    pub ns: Atom,
+    pub local: Atom,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: QualName,
+    pub value: String,
 }
 
-pub fn text(data: String) -> Node {
-    Node { children: Vec::new(), node_type: NodeType::Text(data) }
+/// The classes of an element, read from and written back to its `class` attribute
+pub struct ClassList<'a> {
+    document: &'a mut Document,
+    id: NodeId,
 }
 
-pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
-    Node {
-        children: children,
-        node_type: NodeType::Element(ElementData {
-            tag_name: name,
+/// A handle to an observer of the changes to a `Document`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(usize);
+
+/// A change to a document, like the records of a `MutationObserver`
+#[derive(Debug, Clone, PartialEq)]
+pub enum MutationRecord {
+    /// Nodes were added to or removed from the children of `target`, between
+    /// `previous_sibling` and `next_sibling`
+    ChildList {
+        target: NodeId,
+        added: Vec<NodeId>,
+        removed: Vec<NodeId>,
+        previous_sibling: Option<NodeId>,
+        next_sibling: Option<NodeId>,
+    },
+    /// An attribute was set or removed. `old_value` is `None` if it was added.
+    Attributes {
+        target: NodeId,
+        name: QualName,
+        old_value: Option<String>,
+    },
+    /// The data of a text or comment node changed
+    CharacterData {
+        target: NodeId,
+        old_value: String,
+    },
+}
+
+/// The namespaces the html parser puts names in. Elements of inline `<svg>` and `<math>` are
+/// foreign content and keep the case of their names, like `linearGradient`. The last three are
+/// only used for attributes of foreign elements, like `xlink:href`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+    XLink,
+    Xml,
+    XmlNs,
+}
+
+/// The part of the source a node was parsed from. Elements span from their opening tag to
+/// their closing tag, or to the end of their content if the closing tag is missing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    /// Byte offset of the start of the node, into the source after it was decoded to UTF-8
+    pub start: usize,
+    /// Byte offset just past the end of the node
+    pub end: usize,
+    /// 1-based line of the start of the node
+    pub line: usize,
+    /// 1-based column of the start of the node, counted in characters
+    pub column: usize,
+}
+
+/// How closely a document follows the standards, decided by its doctype. Old doctypes, or
+/// none at all, ask for the quirks of old browsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// The `<!DOCTYPE>` of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Doctype {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+}
+
+impl Document {
+    /// Create a document with nothing but its root node
+    pub fn new () -> Document {
+        let mut root = Node::new(NodeType::Document);
+        root.connected = true;
+        Document {
+            nodes: vec![root],
+            url: None,
+            quirks_mode: QuirksMode::NoQuirks,
+            ids: HashMap::new(),
+            observers: Vec::new()
+        }
+    }
+
+    pub fn doctype (&self) -> Option<&Doctype> {
+        self.children(self.root()).find_map(|child| match self[child].node_type {
+            NodeType::Doctype(ref doctype) => Some(doctype),
+            _ => None
+        })
+    }
+
+    /// Change the doctype, add one before the document element or remove it
+    pub fn set_doctype (&mut self, doctype: Option<Doctype>) {
+        let root = self.root();
+        let node = self.children(root).find(|&child| matches!(self[child].node_type, NodeType::Doctype(_)));
+        match (node, doctype) {
+            (Some(node), Some(doctype)) => self.nodes[node.0].node_type = NodeType::Doctype(doctype),
+            (Some(node), None) => self.remove_child(root, node),
+            (None, Some(doctype)) => {
+                let node = self.doctype_node(doctype);
+                let element = self.document_element();
+                self.insert_before(root, node, element);
+            },
+            (None, None) => {}
+        }
+    }
+
+    /// The address the document was loaded from, if it's known
+    pub fn url (&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    pub fn set_url (&mut self, url: String) {
+        self.url = Some(url);
+    }
+
+    pub fn quirks_mode (&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    pub fn set_quirks_mode (&mut self, quirks_mode: QuirksMode) {
+        self.quirks_mode = quirks_mode;
+    }
+
+    /// The document node, the parent of the `html` element
+    pub fn root (&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// The root element, usually `html`
+    pub fn document_element (&self) -> Option<NodeId> {
+        let root = self.root();
+        self.children(root).find(|&child| matches!(self[child].node_type, NodeType::Element(_)))
+    }
+
+    /// The `head` element, if it's a child of the `html` element
+    pub fn head (&self) -> Option<NodeId> {
+        self.html_child(|name| name == "head")
+    }
+
+    /// The `body` element, or a `frameset` in its place, if it's a child of the `html` element
+    pub fn body (&self) -> Option<NodeId> {
+        self.html_child(|name| name == "body" || name == "frameset")
+    }
+
+    /// The text of the first `title` element, with whitespace stripped and collapsed
+    pub fn title (&self) -> String {
+        let title = self.descendants(self.root()).elements().find(|&id| match self[id].node_type {
+            NodeType::Element(ref element) => element.name.local == "title" && !element.is_foreign(),
+            _ => false
+        });
+        let mut text = String::new();
+        for child in title.iter().flat_map(|&title| self.children(title)) {
+            if let NodeType::Text(ref data) = self[child].node_type {
+                text.push_str(data);
+            }
+        }
+        text.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// The first element in the tree with the given id
+    pub fn get_element_by_id (&self, id: &str) -> Option<NodeId> {
+        match self.ids.get(id) {
+            Some(elements) if elements.len() == 1 => Some(elements[0]),
+            Some(elements) => self.descendants(self.root()).find(|node| elements.contains(node)),
+            None => None
+        }
+    }
+
+    pub fn children (&self, id: NodeId) -> Children<'_> {
+        Children { document: self, next: self[id].first_child }
+    }
+
+    /// Everything below a node in document order, without the node itself
+    pub fn descendants (&self, id: NodeId) -> Descendants<'_> {
+        Descendants { document: self, root: id, next: self[id].first_child }
+    }
+
+    /// Everything below a node with children before their parents, without the node itself
+    pub fn descendants_post_order (&self, id: NodeId) -> DescendantsPostOrder<'_> {
+        DescendantsPostOrder { document: self, root: id, next: self[id].first_child.map(|child| self.first_leaf(child)) }
+    }
+
+    /// The nodes a node is in, starting with its parent
+    pub fn ancestors (&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors { document: self, next: self[id].parent }
+    }
+
+    /// The text of all text nodes below a node, or the data of a text or comment node
+    pub fn text_content (&self, id: NodeId) -> String {
+        match self[id].node_type {
+            NodeType::Text(ref data) | NodeType::Comment(ref data) => data.clone(),
+            _ => {
+                let mut text = String::new();
+                for node in self.descendants(id) {
+                    if let NodeType::Text(ref data) = self[node].node_type {
+                        text.push_str(data);
+                    }
+                }
+                text
+            }
+        }
+    }
+
+    /// Set where a node was found in the parsed source
+    pub fn set_span (&mut self, id: NodeId, span: Option<Span>) {
+        self.nodes[id.0].span = span;
+    }
+
+    // Creating nodes. New nodes are not part of the tree until they are added to a parent.
+
+    pub fn text (&mut self, data: String) -> NodeId {
+        self.add(Node::new(NodeType::Text(data)))
+    }
+
+    pub fn comment (&mut self, data: String) -> NodeId {
+        self.add(Node::new(NodeType::Comment(data)))
+    }
+
+    pub fn doctype_node (&mut self, doctype: Doctype) -> NodeId {
+        self.add(Node::new(NodeType::Doctype(doctype)))
+    }
+
+    /// An html element with attributes that have no namespace
+    pub fn elem (&mut self, name: Atom, attrs: AttrMap, children: Vec<NodeId>) -> NodeId {
+        let attrs = attrs.into_iter().map(|(name, value)| Attribute { name: QualName::from(&*name), value }).collect();
+        self.elem_ns(QualName::new(None, Namespace::Html.atom(), name), attrs, children)
+    }
+
+    pub fn elem_ns (&mut self, name: QualName, attrs: Vec<Attribute>, children: Vec<NodeId>) -> NodeId {
+        let element = self.add(Node::new(NodeType::Element(ElementData {
+            name,
             attributes: attrs,
+            template_contents: None,
+        })));
+        for child in children {
+            self.link(element, child, None);
+        }
+        element
+    }
+
+    /// A fragment holding nodes outside of the tree. Adding it to a parent adds its children.
+    pub fn fragment (&mut self, children: Vec<NodeId>) -> NodeId {
+        let fragment = self.add(Node::new(NodeType::DocumentFragment));
+        for child in children {
+            self.link(fragment, child, None);
+        }
+        fragment
+    }
+
+    /// A `template` element. Its contents are kept in a fragment apart from its children, so
+    /// they are not styled or laid out until they are cloned into the document.
+    pub fn template (&mut self, attrs: AttrMap, contents: Vec<NodeId>) -> NodeId {
+        let fragment = self.fragment(contents);
+        let template = self.elem(Atom::from("template"), attrs, Vec::new());
+        if let NodeType::Element(ref mut element) = self.nodes[template.0].node_type {
+            element.template_contents = Some(fragment);
+        }
+        template
+    }
+
+    /// A copy of a node without a parent, and of everything below it if `deep`. A deep copy of
+    /// a `template` also copies its contents, so they can be cloned into the tree any number
+    /// of times.
+    pub fn clone_node (&mut self, id: NodeId, deep: bool) -> NodeId {
+        let offset = self.nodes.len();
+        let copies = Document::copy_nodes(self, id, deep, offset);
+        self.nodes.extend(copies);
+        NodeId(offset)
+    }
+
+    /// A copy of a node of another document and of everything below it, without a parent
+    pub fn import_node (&mut self, other: &Document, id: NodeId) -> NodeId {
+        let offset = self.nodes.len();
+        let copies = Document::copy_nodes(other, id, true, offset);
+        self.nodes.extend(copies);
+        NodeId(offset)
+    }
+
+    // Changing the tree. A node that is added to a parent is first removed from where it was,
+    // and adding a fragment adds its children instead. Every change is recorded for the
+    // observers of the document.
+
+    /// Add `child` as the last child of `parent`
+    pub fn append_child (&mut self, parent: NodeId, child: NodeId) {
+        self.insert_before(parent, child, None)
+    }
+
+    /// Add `child` to `parent` just before `reference`, or as the last child if there is no
+    /// `reference`.
+    ///
+    /// Panics if `reference` is not a child of `parent`, if `child` is the document or if it
+    /// contains `parent`. Panics as well if `parent` is a text, comment or doctype node, if the
+    /// document would get text, a second element or a second doctype as a child, or if its
+    /// doctype would not come before its element.
+    pub fn insert_before (&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+        if let Some(reference) = reference {
+            assert!(self[reference].parent == Some(parent), "the reference node is not a child of the parent");
+        }
+        self.check_insertion(parent, child, reference, None);
+
+        // Moving a node just before itself leaves it where it is
+        let reference = if reference == Some(child) { self[child].next_sibling } else { reference };
+        let added = self.take_for_insertion(child);
+        self.insert_nodes(parent, added, reference, Vec::new());
+    }
+
+    /// Remove `child` from `parent`. The node stays in the document and can be added again.
+    ///
+    /// Panics if `child` is not a child of `parent`.
+    pub fn remove_child (&mut self, parent: NodeId, child: NodeId) {
+        assert!(self[child].parent == Some(parent), "the node is not a child of the parent");
+        self.remove(child);
+    }
+
+    /// Put `new_child` in the place of `old_child`, which is removed from `parent`.
+    ///
+    /// Panics like `insert_before`, or if `old_child` is not a child of `parent`.
+    pub fn replace_child (&mut self, parent: NodeId, new_child: NodeId, old_child: NodeId) {
+        assert!(self[old_child].parent == Some(parent), "the node is not a child of the parent");
+        if new_child == old_child {
+            return;
+        }
+        self.check_insertion(parent, new_child, None, Some(old_child));
+
+        let mut reference = self[old_child].next_sibling;
+        if reference == Some(new_child) {
+            reference = self[new_child].next_sibling;
+        }
+        let added = self.take_for_insertion(new_child);
+        self.detach(old_child);
+        self.insert_nodes(parent, added, reference, vec![old_child]);
+    }
+
+    /// Set an attribute without a namespace
+    pub fn set_attribute (&mut self, id: NodeId, name: &str, value: &str) {
+        self.set_attribute_ns(id, QualName::from(name), value)
+    }
+
+    pub fn set_attribute_ns (&mut self, id: NodeId, name: QualName, value: &str) {
+        let indexed = name.ns.is_empty() && name.local == "id" && self.is_connected(id);
+        if indexed {
+            self.unindex(id);
+        }
+        let attributes = &mut self.element_mut(id).attributes;
+        let old_value = match attributes.iter_mut().find(|attribute| attribute.name.ns == name.ns && attribute.name.local == name.local) {
+            Some(attribute) => {
+                attribute.name = name.clone();
+                Some(mem::replace(&mut attribute.value, value.to_string()))
+            },
+            None => {
+                attributes.push(Attribute { name: name.clone(), value: value.to_string() });
+                None
+            }
+        };
+        if indexed {
+            self.index(id);
+        }
+        self.record(MutationRecord::Attributes { target: id, name, old_value });
+    }
+
+    /// Remove an attribute without a namespace and return its value, if it had one
+    pub fn remove_attribute (&mut self, id: NodeId, name: &str) -> Option<String> {
+        self.remove_attribute_ns(id, "", name)
+    }
+
+    /// Remove the attribute with the namespace url `ns` and return its value, if it had one
+    pub fn remove_attribute_ns (&mut self, id: NodeId, ns: &str, local: &str) -> Option<String> {
+        let indexed = ns.is_empty() && local == "id" && self.is_connected(id);
+        if indexed {
+            self.unindex(id);
+        }
+        let attributes = &mut self.element_mut(id).attributes;
+        let index = attributes.iter().position(|attribute| attribute.name.ns == ns && attribute.name.local == local)?;
+        let attribute = attributes.remove(index);
+        self.record(MutationRecord::Attributes { target: id, name: attribute.name, old_value: Some(attribute.value.clone()) });
+        Some(attribute.value)
+    }
+
+    /// The classes of an element, to change them. Panics if the node is not an element.
+    pub fn class_list (&mut self, id: NodeId) -> ClassList<'_> {
+        self.element_mut(id);
+        ClassList { document: self, id }
+    }
+
+    /// Set the data of a text or comment node. Elements and fragments get `text` as their only
+    /// child, the way setting `textContent` does, and the document and doctype stay as they are.
+    pub fn set_text (&mut self, id: NodeId, text: &str) {
+        let old_value = match self.nodes[id.0].node_type {
+            NodeType::Text(ref mut data) | NodeType::Comment(ref mut data) => Some(mem::replace(data, text.to_string())),
+            NodeType::Document | NodeType::Doctype(_) => return,
+            _ => None
+        };
+        if let Some(old_value) = old_value {
+            self.record(MutationRecord::CharacterData { target: id, old_value });
+            return;
+        }
+
+        let removed: Vec<NodeId> = self.children(id).collect();
+        for &child in &removed {
+            self.detach(child);
+        }
+        let added = if text.is_empty() { Vec::new() } else { vec![self.text(text.to_string())] };
+        self.insert_nodes(id, added, None, removed);
+    }
+
+    /// Returns true if `descendant` is `ancestor` or is somewhere below it
+    pub fn contains (&self, ancestor: NodeId, descendant: NodeId) -> bool {
+        let mut node = Some(descendant);
+        while let Some(id) = node {
+            if id == ancestor {
+                return true;
+            }
+            node = self[id].parent;
+        }
+        false
+    }
+
+    // Building the tree for the html parser, which puts nodes where the tree construction
+    // rules say without the checks and records of the methods above
+
+    /// Move `child` into `parent`, before `next` or last
+    pub(crate) fn move_node (&mut self, parent: NodeId, child: NodeId, next: Option<NodeId>) {
+        self.detach(child);
+        self.link(parent, child, next);
+    }
+
+    /// Add text to the end of a text node
+    pub(crate) fn append_text (&mut self, id: NodeId, text: &str) {
+        if let NodeType::Text(ref mut data) = self.nodes[id.0].node_type {
+            data.push_str(text);
+        }
+    }
+
+    // Observing changes
+
+    /// Start recording the changes to the document for a new observer
+    pub fn observe (&mut self) -> ObserverId {
+        self.observers.push(Some(Vec::new()));
+        ObserverId(self.observers.len() - 1)
+    }
+
+    /// The changes since the observer last took them, oldest first
+    pub fn take_records (&mut self, observer: ObserverId) -> Vec<MutationRecord> {
+        self.observers[observer.0].as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Stop recording changes for an observer and drop the ones it has not taken
+    pub fn disconnect (&mut self, observer: ObserverId) {
+        self.observers[observer.0] = None;
+    }
+
+    fn record (&mut self, record: MutationRecord) {
+        for records in self.observers.iter_mut().flatten() {
+            records.push(record.clone());
+        }
+    }
+
+    /// Panics if adding `child` to `parent` before `reference`, or in the place of `replaced`,
+    /// would not leave a valid tree, following
+    /// https://dom.spec.whatwg.org/#concept-node-ensure-pre-insertion-validity
+    fn check_insertion (&self, parent: NodeId, child: NodeId, reference: Option<NodeId>, replaced: Option<NodeId>) {
+        assert!(matches!(self[parent].node_type, NodeType::Document | NodeType::DocumentFragment | NodeType::Element(_)),
+                "only documents, fragments and elements can have children");
+        assert!(self[child].node_type != NodeType::Document, "a document can't be a child");
+        assert!(!self.contains(child, parent), "a node can't be added to its own subtree");
+        let is_doctype = |&node: &NodeId| matches!(self[node].node_type, NodeType::Doctype(_));
+        assert!(self[parent].node_type == NodeType::Document || !is_doctype(&child), "a doctype can only be a child of the document");
+
+        if self[parent].node_type == NodeType::Document {
+            let added: Vec<NodeId> = match self[child].node_type {
+                NodeType::DocumentFragment => self.children(child).collect(),
+                _ => vec![child]
+            };
+            // The other children, before and after the place `child` goes to
+            let place = replaced.or(reference);
+            let mut before = Vec::new();
+            let mut after = Vec::new();
+            for node in self.children(parent) {
+                if Some(node) == place || !after.is_empty() {
+                    after.push(node);
+                } else {
+                    before.push(node);
+                }
+            }
+            before.retain(|&node| node != child);
+            after.retain(|&node| node != child && Some(node) != replaced);
+
+            let is_element = |&node: &NodeId| matches!(self[node].node_type, NodeType::Element(_));
+            assert!(added.iter().all(|&node| !matches!(self[node].node_type, NodeType::Text(_))), "the document can't have text as a child");
+            let elements = before.iter().chain(&after).chain(&added).filter(|node| is_element(node)).count();
+            assert!(elements <= 1, "the document can only have one element as a child");
+            let doctypes = before.iter().chain(&after).chain(&added).filter(|node| is_doctype(node)).count();
+            assert!(doctypes <= 1, "the document can only have one doctype as a child");
+            let order = "the doctype of a document has to come before its element";
+            assert!(!added.iter().any(is_doctype) || !before.iter().any(is_element), "{}", order);
+            assert!(!added.iter().any(is_element) || !after.iter().any(is_doctype), "{}", order);
+        }
+    }
+
+    /// Take the nodes that inserting `child` adds out of where they are: the node itself, or
+    /// the children of a fragment
+    fn take_for_insertion (&mut self, child: NodeId) -> Vec<NodeId> {
+        if self[child].node_type == NodeType::DocumentFragment {
+            let children: Vec<NodeId> = self.children(child).collect();
+            for &node in &children {
+                self.detach(node);
+            }
+            if !children.is_empty() {
+                self.record(MutationRecord::ChildList {
+                    target: child, added: Vec::new(), removed: children.clone(), previous_sibling: None, next_sibling: None
+                });
+            }
+            children
+        } else {
+            self.remove(child);
+            vec![child]
+        }
+    }
+
+    /// Add detached nodes before `reference` and record it, together with the nodes that were
+    /// removed from the same place
+    fn insert_nodes (&mut self, parent: NodeId, added: Vec<NodeId>, reference: Option<NodeId>, removed: Vec<NodeId>) {
+        let previous_sibling = match reference {
+            Some(reference) => self[reference].previous_sibling,
+            None => self[parent].last_child
+        };
+        for &node in &added {
+            self.link(parent, node, reference);
+        }
+        if !added.is_empty() || !removed.is_empty() {
+            self.record(MutationRecord::ChildList { target: parent, added, removed, previous_sibling, next_sibling: reference });
+        }
+    }
+
+    /// Detach a node from its parent, if it has one, and record it
+    fn remove (&mut self, id: NodeId) {
+        if let Some(parent) = self[id].parent {
+            let previous_sibling = self[id].previous_sibling;
+            let next_sibling = self[id].next_sibling;
+            self.detach(id);
+            self.record(MutationRecord::ChildList { target: parent, added: Vec::new(), removed: vec![id], previous_sibling, next_sibling });
+        }
+    }
+
+    fn add (&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Panics if the node is not an element
+    fn element_mut (&mut self, id: NodeId) -> &mut ElementData {
+        match self.nodes[id.0].node_type {
+            NodeType::Element(ref mut element) => element,
+            _ => panic!("the node is not an element")
+        }
+    }
+
+    fn html_child<F> (&self, check: F) -> Option<NodeId> where F: Fn(&str) -> bool {
+        let html = self.document_element()?;
+        self.children(html).find(|&child| match self[child].node_type {
+            NodeType::Element(ref element) => !element.is_foreign() && check(&element.name.local),
+            _ => false
         })
     }
+
+    /// The node and everything below it, in document order
+    fn subtree (&self, id: NodeId) -> Vec<NodeId> {
+        Some(id).into_iter().chain(self.descendants(id)).collect()
+    }
+
+    /// The first node without children found by following first children
+    fn first_leaf (&self, mut id: NodeId) -> NodeId {
+        while let Some(child) = self[id].first_child {
+            id = child;
+        }
+        id
+    }
+
+    /// Returns true if the node is part of the tree of the document
+    fn is_connected (&self, id: NodeId) -> bool {
+        self.nodes[id.0].connected
+    }
+
+    /// Add an element to the id index if it has an id
+    fn index (&mut self, id: NodeId) {
+        if let NodeType::Element(ref element) = self.nodes[id.0].node_type {
+            if let Some(key) = element.id().filter(|key| !key.is_empty()) {
+                self.ids.entry(key.to_string()).or_default().push(id);
+            }
+        }
+    }
+
+    fn unindex (&mut self, id: NodeId) {
+        let key = match self.nodes[id.0].node_type {
+            NodeType::Element(ref element) => element.id().map(|key| key.to_string()),
+            _ => None
+        };
+        if let Some(key) = key {
+            let empty = match self.ids.get_mut(&key) {
+                Some(elements) => {
+                    elements.retain(|&element| element != id);
+                    elements.is_empty()
+                },
+                None => false
+            };
+            if empty {
+                self.ids.remove(&key);
+            }
+        }
+    }
+
+    /// Take a node out of its parent's children, if it has a parent
+    fn detach (&mut self, id: NodeId) {
+        if self[id].parent.is_some() && self.is_connected(id) {
+            for node in self.subtree(id) {
+                self.unindex(node);
+                self.nodes[node.0].connected = false;
+            }
+        }
+        let (parent, previous, next) = {
+            let node = &mut self.nodes[id.0];
+            let links = (node.parent, node.previous_sibling, node.next_sibling);
+            node.parent = None;
+            node.previous_sibling = None;
+            node.next_sibling = None;
+            links
+        };
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return
+        };
+        match previous {
+            Some(previous) => self.nodes[previous.0].next_sibling = next,
+            None => self.nodes[parent.0].first_child = next
+        }
+        match next {
+            Some(next) => self.nodes[next.0].previous_sibling = previous,
+            None => self.nodes[parent.0].last_child = previous
+        }
+    }
+
+    /// Add a node that has no parent to the children of `parent`, before `next` or last
+    fn link (&mut self, parent: NodeId, child: NodeId, next: Option<NodeId>) {
+        let previous = match next {
+            Some(next) => self.nodes[next.0].previous_sibling,
+            None => self.nodes[parent.0].last_child
+        };
+        {
+            let node = &mut self.nodes[child.0];
+            node.parent = Some(parent);
+            node.previous_sibling = previous;
+            node.next_sibling = next;
+        }
+        match previous {
+            Some(previous) => self.nodes[previous.0].next_sibling = Some(child),
+            None => self.nodes[parent.0].first_child = Some(child)
+        }
+        match next {
+            Some(next) => self.nodes[next.0].previous_sibling = Some(child),
+            None => self.nodes[parent.0].last_child = Some(child)
+        }
+        if self.is_connected(parent) {
+            for node in self.subtree(child) {
+                self.nodes[node.0].connected = true;
+                self.index(node);
+            }
+        }
+    }
+
+    /// Copies of the nodes of `source` from `id` down, or only of `id` unless `deep`, for
+    /// adding them to the end of `nodes`, which is at `offset`. Template contents are copied
+    /// with their template.
+    fn copy_nodes (source: &Document, id: NodeId, deep: bool, offset: usize) -> Vec<Node> {
+        let mut copies: Vec<Node> = Vec::new();
+        // The nodes still to copy, with the copy of their parent or of the template they are
+        // the contents of
+        let mut stack: Vec<(NodeId, Option<NodeId>, Option<NodeId>)> = vec![(id, None, None)];
+        while let Some((original, parent, template)) = stack.pop() {
+            let copy = NodeId(offset + copies.len());
+            let mut node = Node::new(source[original].node_type.clone());
+            node.span = source[original].span;
+            if let NodeType::Element(ref mut element) = node.node_type {
+                if let Some(contents) = element.template_contents.take() {
+                    stack.push((contents, None, Some(copy)));
+                }
+            }
+            if let Some(parent) = parent {
+                let previous = copies[parent.0 - offset].last_child;
+                node.parent = Some(parent);
+                node.previous_sibling = previous;
+                match previous {
+                    Some(previous) => copies[previous.0 - offset].next_sibling = Some(copy),
+                    None => copies[parent.0 - offset].first_child = Some(copy)
+                }
+                copies[parent.0 - offset].last_child = Some(copy);
+            }
+            if let Some(template) = template {
+                if let NodeType::Element(ref mut element) = copies[template.0 - offset].node_type {
+                    element.template_contents = Some(copy);
+                }
+            }
+            copies.push(node);
+
+            if deep {
+                let mut child = source[original].last_child;
+                while let Some(id) = child {
+                    stack.push((id, Some(copy), None));
+                    child = source[id].previous_sibling;
+                }
+            }
+        }
+        copies
+    }
+}
+
+impl Default for Document {
+    fn default () -> Document {
+        Document::new()
+    }
+}
+
+impl Index<NodeId> for Document {
+    type Output = Node;
+
+    fn index (&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = NodeId;
+
+    fn next (&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document[current].next_sibling;
+        Some(current)
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = NodeId;
+
+    fn next (&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        let document = self.document;
+        // The first child, or else the next sibling of the node or of the closest ancestor
+        // that has one, without leaving the root
+        self.next = document[current].first_child.or_else(|| {
+            let mut node = current;
+            while node != self.root {
+                if let Some(next) = document[node].next_sibling {
+                    return Some(next);
+                }
+                node = document[node].parent?;
+            }
+            None
+        });
+        Some(current)
+    }
+}
+
+impl<'a> Iterator for DescendantsPostOrder<'a> {
+    type Item = NodeId;
+
+    fn next (&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        let document = self.document;
+        // The first leaf of the next sibling, or else the parent once all its children are done
+        self.next = match document[current].next_sibling {
+            Some(next) => Some(document.first_leaf(next)),
+            None => document[current].parent.filter(|&parent| parent != self.root)
+        };
+        Some(current)
+    }
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = NodeId;
+
+    fn next (&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document[current].parent;
+        Some(current)
+    }
+}
+
+impl<'a, I> Iterator for Elements<'a, I> where I: Iterator<Item = NodeId> {
+    type Item = NodeId;
+
+    fn next (&mut self) -> Option<NodeId> {
+        let document = self.document;
+        self.nodes.find(|&id| matches!(document[id].node_type, NodeType::Element(_)))
+    }
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = &'a Attribute;
+
+    fn next (&mut self) -> Option<&'a Attribute> {
+        self.attributes.next()
+    }
+}
+
+// Keeping only the elements of a walk
+impl<'a> Children<'a> {
+    pub fn elements (self) -> Elements<'a, Children<'a>> {
+        Elements { document: self.document, nodes: self }
+    }
+}
+
+impl<'a> Descendants<'a> {
+    pub fn elements (self) -> Elements<'a, Descendants<'a>> {
+        Elements { document: self.document, nodes: self }
+    }
+}
+
+impl<'a> DescendantsPostOrder<'a> {
+    pub fn elements (self) -> Elements<'a, DescendantsPostOrder<'a>> {
+        Elements { document: self.document, nodes: self }
+    }
+}
+
+impl<'a> Ancestors<'a> {
+    pub fn elements (self) -> Elements<'a, Ancestors<'a>> {
+        Elements { document: self.document, nodes: self }
+    }
+}
+
+impl Node {
+    fn new (node_type: NodeType) -> Node {
+        Node {
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+            connected: false,
+            node_type,
+            span: None
+        }
+    }
+
+    pub fn parent (&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn first_child (&self) -> Option<NodeId> {
+        self.first_child
+    }
+
+    pub fn last_child (&self) -> Option<NodeId> {
+        self.last_child
+    }
+
+    pub fn previous_sibling (&self) -> Option<NodeId> {
+        self.previous_sibling
+    }
+
+    pub fn next_sibling (&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+}
+
+impl Namespace {
+    pub fn url (&self) -> &'static str {
+        match *self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::Xml => "http://www.w3.org/XML/1998/namespace",
+            Namespace::XmlNs => "http://www.w3.org/2000/xmlns/"
+        }
+    }
+
+    /// The url as an atom, to put in a `QualName`
+    pub fn atom (&self) -> Atom {
+        Atom::from(self.url())
+    }
+
+    pub fn from_url (url: &str) -> Option<Namespace> {
+        [Namespace::Html, Namespace::Svg, Namespace::MathMl, Namespace::XLink, Namespace::Xml, Namespace::XmlNs]
+            .iter().cloned().find(|namespace| namespace.url() == url)
+    }
+}
+
+impl QualName {
+    pub fn new (prefix: Option<Atom>, ns: Atom, local: Atom) -> QualName {
+        QualName { prefix, ns, local }
+    }
+}
+
+/// A name without a namespace, like most attribute names
+impl<'a> From<&'a str> for QualName {
+    fn from (local: &'a str) -> QualName {
+        QualName::new(None, Atom::from(""), Atom::from(local))
+    }
+}
+
+/// Written the way it appears in markup, like `xlink:href`
+impl fmt::Display for QualName {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref prefix) = self.prefix {
+            write!(f, "{}:", prefix)?;
+        }
+        f.write_str(&self.local)
+    }
 }
 
 impl ElementData {
-    pub fn id (&self) -> Option<&String> {
-        self.attributes.get("id")
+    /// The namespace the parser put the element in, if it is one it knows
+    pub fn namespace (&self) -> Option<Namespace> {
+        Namespace::from_url(&self.name.ns)
+    }
+
+    /// The attributes of the element, in source order
+    pub fn attributes (&self) -> Attributes<'_> {
+        Attributes { attributes: self.attributes.iter() }
+    }
+
+    /// The value of the attribute without a namespace called `name`
+    pub fn get_attribute (&self, name: &str) -> Option<&str> {
+        self.get_attribute_ns("", name)
+    }
+
+    /// The value of the attribute with the namespace url `ns` and the local name `local`
+    pub fn get_attribute_ns (&self, ns: &str, local: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|attribute| attribute.name.ns == ns && attribute.name.local == local)
+            .map(|attribute| &*attribute.value)
+    }
+
+    /// Returns true for elements of inline svg or mathml
+    pub fn is_foreign (&self) -> bool {
+        self.name.ns != Namespace::Html.url()
+    }
+
+    /// The fragment with the inert contents of a `template` element, `None` for other elements
+    pub fn template_contents (&self) -> Option<NodeId> {
+        self.template_contents
+    }
+
+    pub fn id (&self) -> Option<&str> {
+        self.get_attribute("id")
     }
 
     pub fn classes (&self) -> HashSet<&str> {
-        match self.attributes.get("class") {
-            Some(classlist) => classlist.split(' ').collect(),
+        match self.get_attribute("class") {
+            Some(classlist) => classlist.split_ascii_whitespace().collect(),
             None => HashSet::new()
         }
     }
 }
+
+// Class names are separated by any ascii whitespace. Changing the list writes it back with
+// single spaces and without duplicates, like `Element.classList` does. Adding or removing a
+// name that is empty or contains whitespace panics, as it could not be read back.
+impl<'a> ClassList<'a> {
+    pub fn contains (&self, class: &str) -> bool {
+        self.classes().contains(&class)
+    }
+
+    pub fn add (&mut self, class: &str) {
+        check_class(class);
+        let mut classes = self.classes();
+        if !classes.contains(&class) {
+            classes.push(class);
+        }
+        let value = classes.join(" ");
+        self.set(value);
+    }
+
+    pub fn remove (&mut self, class: &str) {
+        check_class(class);
+        let mut classes = self.classes();
+        classes.retain(|&other| other != class);
+        let value = classes.join(" ");
+        if self.value().is_some() {
+            self.set(value);
+        }
+    }
+
+    /// Add the class if it's missing or remove it otherwise, and return true if it was added
+    pub fn toggle (&mut self, class: &str) -> bool {
+        if self.contains(class) {
+            self.remove(class);
+            false
+        } else {
+            self.add(class);
+            true
+        }
+    }
+
+    /// The value of the `class` attribute
+    fn value (&self) -> Option<&str> {
+        match self.document[self.id].node_type {
+            NodeType::Element(ref element) => element.get_attribute("class"),
+            _ => None
+        }
+    }
+
+    fn classes (&self) -> Vec<&str> {
+        let mut classes: Vec<&str> = Vec::new();
+        if let Some(value) = self.value() {
+            for class in value.split_ascii_whitespace() {
+                if !classes.contains(&class) {
+                    classes.push(class);
+                }
+            }
+        }
+        classes
+    }
+
+    fn set (&mut self, value: String) {
+        self.document.set_attribute(self.id, "class", &value);
+    }
+}
+
+fn check_class (class: &str) {
+    assert!(!class.is_empty(), "a class name can't be empty");
+    assert!(!class.contains(|c: char| c.is_ascii_whitespace()), "a class name can't contain whitespace");
+}
@@ -0,0 +1,353 @@
+//! Character encodings of html documents: detecting the encoding of a byte stream following
+//! https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding
+//! and decoding it.
+//!
+//! Missing functionality:
+//! encodings other than UTF-8, UTF-16 and windows-1252
+//! changing the encoding when a `<meta charset>` is found after the prescan
+
+use std::char;
+use std::str;
+
+/// The encodings documents can be decoded from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Also used for documents labelled ISO-8859-1 or ASCII, as browsers do
+    Windows1252
+}
+
+/// How many bytes at the start of a document the `<meta>` prescan looks at
+const PRESCAN_LENGTH: usize = 1024;
+
+/// Characters of windows-1252 bytes 0x80 to 0x9f, the other bytes are the same in ISO-8859-1
+static WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}'
+];
+
+/// Returns the character a windows-1252 byte stands for
+pub fn windows_1252 (byte: u8) -> char {
+    match byte {
+        0x80..=0x9f => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+        _ => byte as char
+    }
+}
+
+impl Encoding {
+    /// Returns the encoding of a label like `utf-8` or `latin1`, as used in `<meta charset>`
+    /// and `Content-Type` headers, if it is one of the supported encodings
+    pub fn for_label (label: &str) -> Option<Encoding> {
+        let label = label.trim_matches(|c| matches!(c, '\t' | '\n' | '\x0c' | '\r' | ' ')).to_ascii_lowercase();
+        match &*label {
+            "unicode-1-1-utf-8" | "unicode11utf8" | "unicode20utf8" | "utf-8" | "utf8" | "x-unicode20utf8" => Some(Encoding::Utf8),
+            "csunicode" | "iso-10646-ucs-2" | "ucs-2" | "unicode" | "unicodefeff" | "utf-16" | "utf-16le" => Some(Encoding::Utf16Le),
+            "unicodefffe" | "utf-16be" => Some(Encoding::Utf16Be),
+            "ansi_x3.4-1968" | "ascii" | "cp1252" | "cp819" | "csisolatin1" | "ibm819" | "iso-8859-1" | "iso-ir-100"
+                | "iso8859-1" | "iso88591" | "iso_8859-1" | "iso_8859-1:1987" | "l1" | "latin1" | "us-ascii"
+                | "windows-1252" | "x-cp1252" => Some(Encoding::Windows1252),
+            _ => None
+        }
+    }
+
+    /// The canonical name of the encoding
+    pub fn name (&self) -> &'static str {
+        match *self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Windows1252 => "windows-1252"
+        }
+    }
+}
+
+/// Determine the encoding of a document from its byte order mark, the charset given by the
+/// transport layer (e.g. the `Content-Type` header), or `<meta>` tags in its first 1024 bytes,
+/// in that order. Documents without any of those are assumed to be windows-1252.
+///
+/// Returns the encoding and the length of the byte order mark, which is not part of the content.
+pub fn sniff (bytes: &[u8], transport_charset: Option<&str>) -> (Encoding, usize) {
+    if bytes.starts_with(b"\xef\xbb\xbf") {
+        return (Encoding::Utf8, 3);
+    }
+    if bytes.starts_with(b"\xfe\xff") {
+        return (Encoding::Utf16Be, 2);
+    }
+    if bytes.starts_with(b"\xff\xfe") {
+        return (Encoding::Utf16Le, 2);
+    }
+
+    let encoding = transport_charset.and_then(Encoding::for_label)
+        .or_else(|| prescan(&bytes[..bytes.len().min(PRESCAN_LENGTH)]))
+        .unwrap_or(Encoding::Windows1252);
+    (encoding, 0)
+}
+
+/// Look for a `<meta charset>` or `<meta http-equiv="content-type">` tag, skipping comments
+/// and the attributes of other tags
+pub fn prescan (bytes: &[u8]) -> Option<Encoding> {
+    let mut position = 0;
+    while position < bytes.len() {
+        let rest = &bytes[position..];
+        if rest.starts_with(b"<!--") {
+            position += find(&rest[2..], b"-->").map_or(rest.len(), |end| end + 5);
+        } else if starts_with_ignore_case(rest, b"<meta") && rest.get(5).is_some_and(|&b| is_whitespace(b) || b == b'/') {
+            position += 5;
+            if let Some(encoding) = prescan_meta(bytes, &mut position) {
+                return Some(encoding);
+            }
+        } else if rest.len() > 2 && (rest[0] == b'<' && rest[1].is_ascii_alphabetic()
+                || rest.starts_with(b"</") && rest[2].is_ascii_alphabetic()) {
+            // Skip the tag name and the attributes, which may contain `>` in quotes
+            while position < bytes.len() && !is_whitespace(bytes[position]) && bytes[position] != b'>' {
+                position += 1;
+            }
+            while get_attribute(bytes, &mut position).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position += rest.iter().position(|&b| b == b'>').map_or(rest.len(), |end| end + 1);
+        } else {
+            position += 1;
+        }
+    }
+    None
+}
+
+/// Read the attributes of a `<meta>` tag and return the encoding it declares
+fn prescan_meta (bytes: &[u8], position: &mut usize) -> Option<Encoding> {
+    let mut names = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value)) = get_attribute(bytes, position) {
+        if names.contains(&name) {
+            continue;
+        }
+        match &*name {
+            "http-equiv" => got_pragma = got_pragma || value == "content-type",
+            "content" if charset.is_none() => {
+                if let Some(label) = charset_from_content(&value) {
+                    charset = Some(Encoding::for_label(&label));
+                    need_pragma = Some(true);
+                }
+            },
+            "charset" => {
+                charset = Some(Encoding::for_label(&value));
+                need_pragma = Some(false);
+            },
+            _ => {}
+        }
+        names.push(name);
+    }
+
+    match need_pragma {
+        None => return None,
+        Some(true) if !got_pragma => return None,
+        _ => {}
+    }
+    // A document that can be read as ASCII is not UTF-16
+    match charset {
+        Some(Some(Encoding::Utf16Le)) | Some(Some(Encoding::Utf16Be)) => Some(Encoding::Utf8),
+        Some(encoding) => encoding,
+        None => None
+    }
+}
+
+/// Read the next attribute of a tag, the way the prescan does it. Names and values are
+/// ASCII-lowercased. Returns `None` at the end of the tag.
+fn get_attribute (bytes: &[u8], position: &mut usize) -> Option<(String, String)> {
+    let next = |position: &usize| bytes.get(*position).cloned();
+
+    while next(position).is_some_and(|b| is_whitespace(b) || b == b'/') {
+        *position += 1;
+    }
+    if next(position).is_none_or(|b| b == b'>') {
+        return None;
+    }
+
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    loop {
+        match next(position) {
+            None => return Some((lossy(&name), lossy(&value))),
+            Some(b'=') if !name.is_empty() => {
+                *position += 1;
+                break;
+            },
+            Some(b) if is_whitespace(b) => {
+                while next(position).is_some_and(is_whitespace) {
+                    *position += 1;
+                }
+                if next(position) != Some(b'=') {
+                    return Some((lossy(&name), lossy(&value)));
+                }
+                *position += 1;
+                break;
+            },
+            Some(b'/') | Some(b'>') => return Some((lossy(&name), lossy(&value))),
+            Some(b) => {
+                name.push(b.to_ascii_lowercase());
+                *position += 1;
+            }
+        }
+    }
+
+    while next(position).is_some_and(is_whitespace) {
+        *position += 1;
+    }
+    match next(position) {
+        Some(quote) if quote == b'"' || quote == b'\'' => {
+            *position += 1;
+            while let Some(b) = next(position) {
+                *position += 1;
+                if b == quote {
+                    break;
+                }
+                value.push(b.to_ascii_lowercase());
+            }
+        },
+        Some(b'>') | None => {},
+        Some(_) => {
+            while let Some(b) = next(position) {
+                if is_whitespace(b) || b == b'>' {
+                    break;
+                }
+                value.push(b.to_ascii_lowercase());
+                *position += 1;
+            }
+        }
+    }
+    Some((lossy(&name), lossy(&value)))
+}
+
+/// Find the charset in the `content` attribute of a pragma like
+/// `<meta http-equiv="content-type" content="text/html; charset=utf-8">`
+fn charset_from_content (content: &str) -> Option<String> {
+    let is_space = |c: char| c.is_ascii() && is_whitespace(c as u8);
+    let mut rest = content;
+    loop {
+        let index = rest.to_ascii_lowercase().find("charset")?;
+        rest = rest[index + "charset".len()..].trim_start_matches(is_space);
+        if let Some(after) = rest.strip_prefix('=') {
+            rest = after.trim_start_matches(is_space);
+            break;
+        }
+    }
+
+    match rest.chars().next() {
+        Some(quote) if quote == '"' || quote == '\'' => {
+            let end = rest[1..].find(quote)?;
+            Some(rest[1..end + 1].to_string())
+        },
+        Some(_) => {
+            let end = rest.find(|c| c == ';' || is_space(c)).unwrap_or(rest.len());
+            Some(rest[..end].to_string())
+        },
+        None => None
+    }
+}
+
+fn is_whitespace (b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn starts_with_ignore_case (bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find (haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn lossy (bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Decodes a document that arrives in chunks, which may end in the middle of a character
+#[derive(Debug)]
+pub struct Decoder {
+    encoding: Encoding,
+    /// Bytes at the end of the last chunk that don't form a complete character yet
+    incomplete: Vec<u8>
+}
+
+impl Decoder {
+    pub fn new (encoding: Encoding) -> Decoder {
+        Decoder {
+            encoding,
+            incomplete: Vec::new()
+        }
+    }
+
+    pub fn encoding (&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Decode as much of the input so far as possible. Invalid sequences are replaced by U+FFFD.
+    pub fn decode (&mut self, bytes: &[u8]) -> String {
+        self.incomplete.extend_from_slice(bytes);
+        let (text, used) = match self.encoding {
+            Encoding::Utf8 => decode_utf8(&self.incomplete),
+            Encoding::Utf16Le => decode_utf16(&self.incomplete, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(&self.incomplete, u16::from_be_bytes),
+            Encoding::Windows1252 => (self.incomplete.iter().map(|&b| windows_1252(b)).collect(), self.incomplete.len())
+        };
+        self.incomplete.drain(..used);
+        text
+    }
+
+    /// Returns what is left at the end of the input, an incomplete character becomes U+FFFD
+    pub fn finish (&mut self) -> String {
+        if self.incomplete.is_empty() {
+            String::new()
+        } else {
+            self.incomplete.clear();
+            "\u{fffd}".to_string()
+        }
+    }
+}
+
+/// Decode UTF-8 up to an incomplete character at the end. Returns the text and how many
+/// bytes it was decoded from.
+fn decode_utf8 (bytes: &[u8]) -> (String, usize) {
+    let mut text = String::new();
+    let mut start = 0;
+    loop {
+        match str::from_utf8(&bytes[start..]) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return (text, bytes.len());
+            },
+            Err(error) => {
+                let valid_end = start + error.valid_up_to();
+                text.push_str(str::from_utf8(&bytes[start..valid_end]).unwrap());
+                match error.error_len() {
+                    Some(length) => {
+                        text.push('\u{fffd}');
+                        start = valid_end + length;
+                    },
+                    None => return (text, valid_end)
+                }
+            }
+        }
+    }
+}
+
+/// Decode UTF-16 up to an incomplete code unit or surrogate pair at the end. Returns the text
+/// and how many bytes it was decoded from.
+fn decode_utf16 (bytes: &[u8], unit: fn([u8; 2]) -> u16) -> (String, usize) {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]])).collect();
+    // Keep a high surrogate at the end for the next chunk, its low surrogate may follow
+    let complete = match units.last() {
+        Some(&last) if (0xd800..0xdc00).contains(&last) => units.len() - 1,
+        _ => units.len()
+    };
+    let text = char::decode_utf16(units[..complete].iter().cloned())
+        .map(|c| c.unwrap_or('\u{fffd}'))
+        .collect();
+    (text, complete * 2)
+}
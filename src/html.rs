@@ -1,21 +1,234 @@
 //! Missing functionality:
-//! comments
-//! doctypes and processing instructions
-//! self-closing tags
-//! non-well-formed markup
-//! character entities
+//! scripting
 
-use parser::{ Parser, HtmlParser };
+use std::mem;
+use tokenizer::{ Tokenizer, Token, ContentModel };
+use encoding::{ self, Encoding, Decoder };
+use tree_builder::TreeBuilder;
 use dom;
 
-/// Parse a whole html file and return the root node
-pub fn parse (source: String) -> dom::Node {
-    let mut nodes = Parser::new(source).parse_nodes();
+/// The kinds of malformed input the parser can recover from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// The input ended in the middle of a construct
+    UnexpectedEof,
+    /// A character that cannot appear at this point in the markup
+    UnexpectedCharacter(char),
+    /// A U+0000 NULL character in the input
+    UnexpectedNullCharacter,
+    /// A `<` or `</` that is not followed by a tag name
+    MissingTagName,
+    /// An attribute with a `=` that is not followed by a value
+    MissingAttributeValue(String),
+    /// A second attribute with the same name on one tag, which is ignored
+    DuplicateAttribute(String),
+    /// A closing tag with attributes, which are ignored
+    EndTagWithAttributes,
+    /// A closing tag ending in `/>`
+    EndTagWithTrailingSolidus,
+    /// A `<!` or `<?` construct that is not a comment or doctype, parsed as a comment instead
+    BogusComment,
+    /// An empty comment closed by `<!-->` or `<!--->`
+    AbruptClosingOfEmptyComment,
+    /// A `<!--` inside of a comment
+    NestedComment,
+    /// A comment closed by `--!>`
+    IncorrectlyClosedComment,
+    /// A doctype that does not follow the doctype syntax
+    MalformedDoctype,
+    /// A doctype that is not at the start of the document
+    UnexpectedDoctype,
+    /// A `/>` at the end of an opening tag of an element that is not void, which is ignored
+    SelfClosingNonVoidElement(String),
+    /// A document without a doctype
+    MissingDoctype,
+    /// Text where the document structure does not allow any, e.g. directly inside a table
+    UnexpectedText,
+    /// An opening tag that is not allowed where it appears and is ignored or moved
+    UnexpectedStartTag(String),
+    /// A closing tag that closes an element while some of its descendants are still open,
+    /// or that is nested incorrectly with formatting elements like `<b>` or `<i>`
+    MisnestedTag(String),
+    /// A character reference like `&amp` without the trailing semicolon
+    MissingSemicolonAfterCharacterReference,
+    /// A character reference like `&foo;` with an unknown name
+    UnknownNamedCharacterReference,
+    /// A numeric character reference like `&#;` without any digits
+    AbsenceOfDigitsInNumericCharacterReference,
+    /// A numeric character reference to a code point that can't appear in a document
+    InvalidCharacterReference(u32),
+    /// An element that was still open when the input ended
+    UnclosedElement(String),
+    /// A closing tag that does not match any open element
+    UnexpectedClosingTag(String)
+}
+
+/// A recoverable error together with the position in the input it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset into the decoded source, see `ParseResult::source`
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, counted in characters
+    pub column: usize
+}
+
+/// Elements that can't have any content and have no closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr"
+];
+
+/// Returns true if elements with the given tag name are void, e.g. `<br>` or `<img>`
+pub fn is_void_element (tag_name: &str) -> bool {
+    VOID_ELEMENTS.contains(&tag_name)
+}
+
+/// The result of parsing a document: the best-effort tree and every error found on the way
+#[derive(Debug)]
+pub struct ParseResult {
+    pub document: dom::Document,
+    /// The encoding the document was decoded from
+    pub encoding: Encoding,
+    pub errors: Vec<ParseError>,
+    /// The document decoded to UTF-8, without a byte order mark. The spans of nodes and the
+    /// offsets of errors are positions in this text, not in the bytes that were parsed.
+    pub source: String
+}
+
+/// Parse a whole html file and return the document together with any parse errors.
+/// Malformed markup is corrected the way browsers do it, so there is always a root `html`
+/// element with a `head` and a `body`.
+pub fn parse (source: String) -> ParseResult {
+    let mut parser = Parser::new();
+    parser.feed(source.as_bytes());
+    parser.finish()
+}
+
+/// Parse html as the content of a `context_tag` element, the way setting its `innerHTML`
+/// does, and return the nodes it makes. Only `html` is treated as a document, so for a context
+/// like `div` there is no implied `head` or `body`. A context of `svg` or `math` parses the
+/// content as foreign content. The nodes are created in `document` without a parent, and are
+/// returned together with the parse errors. Their positions are relative to `source`.
+pub fn parse_fragment (document: &mut dom::Document, source: &str, context_tag: &str) -> (Vec<dom::NodeId>, Vec<ParseError>) {
+    let context_tag = context_tag.to_ascii_lowercase();
+    let mut parser = Parser {
+        tokenizer: Tokenizer::new(),
+        builder: TreeBuilder::for_fragment(&context_tag),
+        decoder: Decoder::new(Encoding::Utf8)
+    };
+    parser.tokenizer.set_content_model(match &*context_tag {
+        "title" | "textarea" => ContentModel::RcData,
+        "style" | "xmp" | "iframe" | "noembed" | "noframes" => ContentModel::RawText,
+        "script" => ContentModel::ScriptData,
+        "plaintext" => ContentModel::PlainText,
+        _ => ContentModel::Data
+    });
+    parser.tokenizer.set_last_start_tag(&context_tag);
+    parser.tokenizer.set_allow_cdata(parser.builder.in_foreign_content());
+
+    parser.tokenizer.feed(source);
+    parser.tokenizer.end();
+    parser.build();
+    let errors = parser.errors();
+    (parser.builder.finish_fragment(document), errors)
+}
+
+/// Parse a html file that has not been decoded yet. The encoding is taken from a byte order
+/// mark, the `transport_charset` (e.g. from the `Content-Type` header) or a `<meta charset>`
+/// near the start of the document, see `encoding::sniff`. Positions in the result refer to the
+/// decoded `ParseResult::source`.
+pub fn parse_bytes (bytes: &[u8], transport_charset: Option<&str>) -> ParseResult {
+    let (encoding, bom_length) = encoding::sniff(bytes, transport_charset);
+    let mut parser = Parser::with_encoding(encoding);
+    parser.feed(&bytes[bom_length..]);
+    parser.finish()
+}
+
+/// An html parser that takes the document in chunks as they arrive, e.g. from the network,
+/// and builds the tree as far as the input allows.
+#[derive(Debug)]
+pub struct Parser {
+    tokenizer: Tokenizer,
+    builder: TreeBuilder,
+    decoder: Decoder
+}
+
+impl Default for Parser {
+    fn default () -> Parser {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    /// Create a parser for a UTF-8 document
+    pub fn new () -> Parser {
+        Parser::with_encoding(Encoding::Utf8)
+    }
+
+    /// Create a parser for a document in the given encoding. A chunk may end in the middle of
+    /// a character.
+    pub fn with_encoding (encoding: Encoding) -> Parser {
+        Parser {
+            tokenizer: Tokenizer::new(),
+            builder: TreeBuilder::new(),
+            decoder: Decoder::new(encoding)
+        }
+    }
+
+    /// Parse the next chunk of the document
+    pub fn feed (&mut self, bytes: &[u8]) {
+        let text = self.decoder.decode(bytes);
+        self.tokenizer.feed(&text);
+        self.build();
+    }
+
+    /// The document built from the input so far, which is the tree the parser keeps building
+    /// on, so looking at it costs nothing. Elements that are still open may get more content
+    /// with the next chunks, and their spans end with their opening tag until they are closed
+    /// or the parser is finished.
+    pub fn document (&self) -> &dom::Document {
+        self.builder.document()
+    }
+
+    /// Parse what's left of the input and return the finished document
+    pub fn finish (mut self) -> ParseResult {
+        let encoding = self.decoder.encoding();
+        let rest = self.decoder.finish();
+        self.tokenizer.feed(&rest);
+        self.tokenizer.end();
+        self.build();
+
+        let errors = self.errors();
+        ParseResult {
+            document: self.builder.finish(),
+            encoding,
+            errors,
+            source: self.tokenizer.into_input()
+        }
+    }
+
+    /// The errors of the tokenizer and the tree builder, in the order of the input
+    fn errors (&mut self) -> Vec<ParseError> {
+        let mut errors = mem::take(&mut self.tokenizer.errors);
+        errors.append(&mut self.builder.errors);
+        errors.sort_by_key(|error| error.offset);
+        errors
+    }
 
-    // If there is a root element return it, otherwise create one
-    if nodes.len() == 1 {
-        nodes.swap_remove(0)
-    } else {
-        dom::elem("html".to_string(), dom::AttrMap::new(), nodes)
+    /// Hand every token the tokenizer can produce from the input so far to the tree builder
+    fn build (&mut self) {
+        while let Some((token, span)) = self.tokenizer.next_token() {
+            let eof = token == Token::Eof;
+            if let Some(content_model) = self.builder.process_token(token, span) {
+                self.tokenizer.set_content_model(content_model);
+            }
+            self.tokenizer.set_allow_cdata(self.builder.in_foreign_content());
+            if eof {
+                break;
+            }
+        }
     }
 }
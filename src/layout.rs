@@ -2,6 +2,7 @@
 
 use css::{ Value, Unit };
 use style::{ StyledNode };
+use dom::NodeType;
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Dimensions {
@@ -34,6 +35,9 @@ pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
+    /// The text of a text node as it is rendered, with whitespace collapsed across the inline
+    /// content it is part of. `None` for other boxes.
+    pub text: Option<String>,
 }
 
 pub enum BoxType<'a> {
@@ -48,6 +52,15 @@ pub enum Display {
     None,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+}
+
 impl<'a> StyledNode<'a> {
     /// Return the specified value of a property if it exists, otherwise `None`.
     pub fn value (&self, name: &str) -> Option<Value> {
@@ -72,6 +85,133 @@ impl<'a> StyledNode<'a> {
             _ => Display::Inline
         }
     }
+
+    /// The value of the `white-space` property (defaults to normal).
+    pub fn white_space (&self) -> WhiteSpace {
+        match self.value("white-space") {
+            Some(Value::Keyword(s)) => match &*s {
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                "pre-line" => WhiteSpace::PreLine,
+                _ => WhiteSpace::Normal
+            },
+            _ => WhiteSpace::Normal
+        }
+    }
+
+    /// Whether this is a text node of only whitespace that collapses to a single space
+    fn is_collapsible_whitespace (&self) -> bool {
+        match (&self.node.node_type, self.white_space()) {
+            (&NodeType::Text(ref text), WhiteSpace::Normal) | (&NodeType::Text(ref text), WhiteSpace::Nowrap) => {
+                text.chars().all(is_whitespace)
+            },
+            _ => false
+        }
+    }
+}
+
+fn is_whitespace (c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0c')
+}
+
+/// Collapse the whitespace of the text boxes of an inline formatting context, following
+/// http://www.w3.org/TR/css-text-3/#white-space-phase-1
+///
+/// Sequences of whitespace become a single space, also when they are spread over several
+/// boxes like in `<b>a </b> <i> b</i>`, and whitespace at the start and end of the context is
+/// removed. `pre-line` keeps line breaks, `pre` and `pre-wrap` keep all whitespace.
+fn collapse_whitespace (boxes: Vec<&mut LayoutBox>) {
+    // Whether the text so far ends in a space that following whitespace collapses into, which
+    // is also the case at the start
+    let mut collapsed = true;
+    // The box whose text ends in such a space, which is removed at a line break or the end
+    let mut trailing_space: Option<usize> = None;
+    let mut texts: Vec<String> = Vec::new();
+
+    for layout_box in &boxes {
+        let style = layout_box.get_style_node();
+        let text = match style.node.node_type {
+            NodeType::Text(ref text) => &**text,
+            _ => ""
+        };
+        let keep_newlines = match style.white_space() {
+            WhiteSpace::Pre | WhiteSpace::PreWrap => {
+                if !text.is_empty() {
+                    collapsed = false;
+                    trailing_space = None;
+                }
+                texts.push(text.to_string());
+                continue;
+            },
+            WhiteSpace::PreLine => true,
+            WhiteSpace::Normal | WhiteSpace::Nowrap => false
+        };
+
+        let mut result = String::new();
+        for c in text.chars() {
+            if keep_newlines && c == '\n' {
+                // Spaces before a line break are removed
+                if result.ends_with(' ') {
+                    result.pop();
+                } else if let Some(index) = trailing_space {
+                    texts[index].pop();
+                }
+                trailing_space = None;
+                result.push('\n');
+                collapsed = true;
+            } else if is_whitespace(c) {
+                if !collapsed {
+                    result.push(' ');
+                    collapsed = true;
+                }
+            } else {
+                result.push(c);
+                collapsed = false;
+            }
+        }
+        if result.ends_with(' ') {
+            trailing_space = Some(texts.len());
+        } else if !result.is_empty() {
+            trailing_space = None;
+        }
+        texts.push(result);
+    }
+    if let Some(index) = trailing_space {
+        texts[index].pop();
+    }
+
+    for (layout_box, text) in boxes.into_iter().zip(texts) {
+        layout_box.text = Some(text);
+    }
+}
+
+/// Collect the text boxes of the inline content of `layout_box`, leaving out nested blocks,
+/// which are formatting contexts of their own
+fn text_boxes<'a, 'b> (layout_box: &'b mut LayoutBox<'a>, boxes: &mut Vec<&'b mut LayoutBox<'a>>) {
+    for child in &mut layout_box.children {
+        match child.box_type {
+            BoxType::BlockNode(_) => {},
+            BoxType::InlineNode(node) if matches!(node.node.node_type, NodeType::Text(_)) => boxes.push(child),
+            _ => text_boxes(child, boxes)
+        }
+    }
+}
+
+/// Collapse the whitespace of the inline content of a box. The inline content of a block is
+/// in its anonymous blocks, each of which is an inline formatting context.
+fn collapse_inline_whitespace (layout_box: &mut LayoutBox) {
+    if let BoxType::BlockNode(_) = layout_box.box_type {
+        for child in &mut layout_box.children {
+            if let BoxType::AnonymousBlock = child.box_type {
+                collapse_inline_whitespace(child);
+            }
+        }
+    } else {
+        let mut boxes = Vec::new();
+        text_boxes(layout_box, &mut boxes);
+        collapse_whitespace(boxes);
+    }
 }
 
 /// Transform a style tree into a layout tree.
@@ -81,6 +221,9 @@ pub fn layout_tree<'a> (node: &'a StyledNode<'a>, mut containing_block: Dimensio
     containing_block.content.height = 0.0;
 
     let mut root_box = build_layout_tree(node);
+    if let BoxType::InlineNode(_) = root_box.box_type {
+        collapse_inline_whitespace(&mut root_box);
+    }
     root_box.layout(containing_block);
     root_box
 }
@@ -94,8 +237,25 @@ fn build_layout_tree<'a> (style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
         Display::None => panic!("Root node has display: none.")
     });
 
+    // Svg and mathml are not rendered, their elements are laid out as empty boxes.
+    if let NodeType::Element(ref element) = style_node.node.node_type {
+        if element.is_foreign() {
+            return root;
+        }
+    }
+
     // Create the descendant boxes.
-    for child in &style_node.children {
+    let children = &style_node.children;
+    for (i, child) in children.iter().enumerate() {
+        // Whitespace between blocks or at the start or end of a block is not rendered, only
+        // whitespace between inline content is. Inside of inline elements it is collapsed
+        // with the text around the element.
+        let inline = |sibling: Option<&StyledNode>| matches!(sibling.map(|sibling| sibling.display()), Some(Display::Inline));
+        let block = matches!(root.box_type, BoxType::BlockNode(_));
+        if block && child.is_collapsible_whitespace() && !(i > 0 && inline(children.get(i - 1)) && inline(children.get(i + 1))) {
+            continue;
+        }
+
         match child.display() {
             Display::Block => {
                 root.children.push(build_layout_tree(child));
@@ -106,6 +266,9 @@ fn build_layout_tree<'a> (style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
             Display::None => {} // Skip nodes with `display: none;`
         }
     }
+    if let BoxType::BlockNode(_) = root.box_type {
+        collapse_inline_whitespace(&mut root);
+    }
     root
 }
 
@@ -116,6 +279,7 @@ impl<'a> LayoutBox<'a> {
             box_type: box_type,
             dimensions: Default::default(), // initially set all fields to 0.0
             children: Vec::new(),
+            text: None,
         }
     }
 
@@ -2,8 +2,14 @@ extern crate sdl2;
 use sdl2::render::Renderer;
 
 // parsing and layout generation
+pub mod atom;
 pub mod dom;
 pub mod html;
+pub mod serializer;
+pub mod tokenizer;
+pub mod tree_builder;
+pub mod entities;
+pub mod encoding;
 pub mod css;
 pub mod style;
 pub mod layout;
@@ -1,31 +1,79 @@
-use std::ascii::AsciiExt;
-use std::borrow::Borrow;
-use dom::{ self, Node, AttrMap };
-use css::{ Value, Unit, Color, Declaration, SimpleSelector, Selector, Rule };
+use std::collections::HashMap;
+use css::{ Stylesheet, Value, Unit, Color, Declaration, SimpleSelector, Selector, Rule };
+use atom::Atom;
 
+/// A cursor over the bytes of a stylesheet. Everything css gives meaning to is ASCII, so the
+/// input is scanned byte by byte and consumed text is returned as slices of the input.
 #[derive(Debug)]
-pub struct Parser {
+pub struct Parser<'a> {
     pos: usize,
-    input: String
+    input: &'a str,
+    /// The namespace urls declared with `@namespace`, by prefix
+    namespaces: HashMap<String, Atom>,
+    default_namespace: Option<Atom>
 }
 
-impl Parser {
-    pub fn new(source: String) -> Parser {
+/// The kinds of selectors that can't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectorErrorKind {
+    /// A selector with nothing in it, like the whole of `""` or the end of `p,`
+    EmptySelector,
+    /// A character that can't continue a selector, like a combinator, `[` or `:`
+    UnexpectedCharacter(char),
+    /// The input ended after a `#`, `.` or `|` that needs a name
+    UnexpectedEof,
+    /// A namespace prefix that wasn't declared with `@namespace`
+    UndeclaredNamespacePrefix(String)
+}
+
+/// A selector that can't be parsed, with the byte offset into the input it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    pub kind: SelectorErrorKind,
+    pub offset: usize
+}
+
+/// Parse a stylesheet
+pub fn parse_stylesheet (source: &str) -> Stylesheet {
+    Stylesheet {
+        rules: Parser::new(source).parse_rules()
+    }
+}
+
+/// Parse a comma separated list of selectors, like `p.note, #main`. The whole of `source` has
+/// to be selectors, and each of them has to have something in it.
+pub fn parse_selector_list (source: &str) -> Result<Vec<Selector>, SelectorError> {
+    let mut parser = Parser::new(source);
+    parser.consume_whitespace();
+    let selectors = parser.parse_selectors()?;
+    match parser.next_char() {
+        Some(c) => Err(parser.selector_error(SelectorErrorKind::UnexpectedCharacter(c))),
+        None => Ok(selectors)
+    }
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str) -> Parser<'a> {
         Parser {
             pos: 0,
-            input: source
+            input: source,
+            namespaces: HashMap::new(),
+            default_namespace: None
         }
     }
 
-    /// Returns the next character from the input
-    fn next_char (&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+    /// Returns the next byte of the input, or `None` at the end of the input
+    fn next_byte (&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).cloned()
     }
 
-    /// Returns true if the input from the current position starts with the given String
-    ///         false otherwise
-    fn starts_with (&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
+    /// Returns the next character from the input, or `None` at the end of the input
+    fn next_char (&self) -> Option<char> {
+        match self.next_byte() {
+            Some(b) if b.is_ascii() => Some(b as char),
+            Some(_) => self.input[self.pos..].chars().next(),
+            None => None
+        }
     }
 
     /// Returns true if the end of the input has been reached
@@ -33,132 +81,46 @@ impl Parser {
         self.pos >= self.input.len()
     }
 
-    /// Consumes and returns the next character of the input
-    fn consume_char (&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += next_pos;
+    /// Consumes and returns the next character of the input, or `None` at the end of the input
+    fn consume_char (&mut self) -> Option<char> {
+        let cur_char = self.next_char();
+        if let Some(c) = cur_char {
+            self.pos += c.len_utf8();
+        }
         cur_char
     }
 
-    /// Consumes and returns as strings characters while they pass the test function
-    fn consume_while (&mut self, check: &Fn (char) -> bool) -> String {
-        let mut result = String::new();
-        while !self.eof() && check(self.next_char()) {
-            result.push(self.consume_char());
+    /// Consumes bytes while they pass the test function and returns them as a slice of the
+    /// input. Only ASCII bytes are passed to `check`, the rest end the slice.
+    fn consume_while<F> (&mut self, check: F) -> &'a str where F: Fn(u8) -> bool {
+        let start = self.pos;
+        let bytes = self.input.as_bytes();
+        while self.pos < bytes.len() && bytes[self.pos].is_ascii() && check(bytes[self.pos]) {
+            self.pos += 1;
         }
-        result
+        &self.input[start..self.pos]
     }
 
     /// Consume all whitespaces from the current position
     fn consume_whitespace (&mut self) {
-        self.consume_while(&char::is_whitespace);
-    }
-
-    /// Consume a tag or attribute name
-    fn parse_tag_name (&mut self) -> String {
-        self.consume_while(&|c| match c {
-            'a'...'z' | 'A'...'Z' | '0'...'9' => true,
-            _ => false
-        })
-    }
-}
-
-pub trait HtmlParser {
-    fn parse_nodes (&mut self) -> Vec<Node>;
-    fn parse_node (&mut self) -> Node;
-    fn parse_text (&mut self) -> Node;
-    fn parse_element (&mut self) -> Node;
-    fn parse_attr (&mut self) -> (String, String);
-    fn parse_attr_value (&mut self) -> String;
-    fn parse_attributes (&mut self) -> AttrMap;
-}
-
-impl HtmlParser for Parser {
-    /// Parse and return in a vec all nodes
-    fn parse_nodes (&mut self) -> Vec<Node> {
-        let mut nodes = Vec::new();
-        loop {
-            self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
-                break;
-            }
-            nodes.push(self.parse_node());
-        }
-        nodes
-    }
-
-    /// Parse and return a node
-    fn parse_node (&mut self) -> Node {
-        match self.next_char() {
-            '<' => self.parse_element(),
-            _ => self.parse_text()
-        }
-    }
-
-    /// Parse and return a text node
-    fn parse_text (&mut self) -> Node {
-        dom::text(self.consume_while(&|c| c != '<'))
-    }
-
-    /// Parse and return an element node
-    fn parse_element (&mut self) -> Node {
-        // Opening tag
-        assert!(self.consume_char() == '<');
-        let tag_name = self.parse_tag_name();
-        let attrs = self.parse_attributes();
-        assert!(self.consume_char() == '>');
-
-        // Children
-        let children = self.parse_nodes();
-
-        // Closing tag
-        assert!(self.consume_char() == '<');
-        assert!(self.consume_char() == '/');
-        assert!(self.parse_tag_name() == tag_name);
-        assert!(self.consume_char() == '>');
-
-        dom::elem(tag_name, attrs, children)
+        self.consume_while(|b| b.is_ascii_whitespace());
     }
 
-    /// Parse and return attribute name and value
-    fn parse_attr (&mut self) -> (String, String) {
-        let name = self.parse_tag_name();
-        assert!(self.consume_char() == '=');
-        let value = self.parse_attr_value();
-        (name, value)
-    }
-
-    /// Parse and return attribute value
-    fn parse_attr_value (&mut self) -> String {
-        let open_quote = self.consume_char();
-        assert!(open_quote == '"' || open_quote == '\'');
-        let value = self.consume_while(&|c| c!= open_quote);
-        assert!(self.consume_char() == open_quote);
-        value
-    }
-
-    /// Parse and return all attributes
-    fn parse_attributes (&mut self) -> AttrMap {
-        let mut attributes = AttrMap::new();
-        loop {
-            self.consume_whitespace();
-            if self.next_char() == '>' {
-                break;
-            }
-            let (name, value) = self.parse_attr();
-            attributes.insert(name, value);
-        }
-        attributes
+    /// An error at the current position
+    fn selector_error (&self, kind: SelectorErrorKind) -> SelectorError {
+        SelectorError { kind, offset: self.pos }
     }
 }
 
 trait CssParser {
     fn parse_rules (&mut self) -> Vec<Rule>;
     fn parse_rule (&mut self) -> Rule;
-    fn parse_selectors (&mut self) -> Vec<Selector>;
-    fn parse_simple_selector (&mut self) -> SimpleSelector;
+    fn parse_at_rule (&mut self);
+    fn parse_url (&mut self) -> String;
+    fn parse_selectors (&mut self) -> Result<Vec<Selector>, SelectorError>;
+    fn parse_simple_selector (&mut self) -> Result<SimpleSelector, SelectorError>;
+    fn parse_type_name (&mut self, selector: &mut SimpleSelector) -> Result<(), SelectorError>;
+    fn parse_selector_name (&mut self) -> Result<&str, SelectorError>;
     fn parse_declarations (&mut self) -> Vec<Declaration>;
     fn parse_declaration (&mut self) -> Declaration;
     fn parse_value (&mut self) -> Value;
@@ -167,11 +129,11 @@ trait CssParser {
     fn parse_unit (&mut self) -> Unit;
     fn parse_color (&mut self) -> Value;
     fn parse_hex_pair (&mut self) -> u8;
-    fn parse_identifier (&mut self) -> String;
-    fn valid_indentifier_char (c: char) -> bool;
+    fn parse_identifier (&mut self) -> &str;
+    fn valid_indentifier_char (b: u8) -> bool;
 }
 
-impl CssParser for Parser {
+impl<'a> CssParser for Parser<'a> {
     fn parse_rules (&mut self) -> Vec<Rule> {
         let mut rules = Vec::new();
         loop {
@@ -179,70 +141,180 @@ impl CssParser for Parser {
             if self.eof() {
                 break;
             }
-            rules.push(self.parse_rule());
+            if self.next_char() == Some('@') {
+                self.parse_at_rule();
+            } else {
+                rules.push(self.parse_rule());
+            }
         }
         rules
     }
 
     fn parse_rule (&mut self) -> Rule {
         Rule {
-            selectors: self.parse_selectors(),
+            selectors: match self.parse_selectors() {
+                Ok(selectors) => selectors,
+                Err(error) => panic!("Invalid selector: {:?}", error)
+            },
             declarations: self.parse_declarations()
         }
     }
 
-    fn parse_selectors (&mut self) -> Vec<Selector> {
+    /// Parse an at-rule. Only `@namespace prefix url(...);` is supported, which has no prefix
+    /// for the default namespace.
+    fn parse_at_rule (&mut self) {
+        assert!(self.consume_char() == Some('@'));
+        let name = self.parse_identifier();
+        if !name.eq_ignore_ascii_case("namespace") {
+            panic!("Unsupported at-rule @{}", name);
+        }
+        self.consume_whitespace();
+        let start = self.pos;
+        let prefix = self.parse_identifier().to_string();
+        let prefix = if prefix.is_empty() || self.next_char() == Some('(') {
+            // That was the start of `url(`
+            self.pos = start;
+            None
+        } else {
+            self.consume_whitespace();
+            Some(prefix)
+        };
+        let url = Atom::from(self.parse_url());
+        self.consume_whitespace();
+        assert!(self.consume_char() == Some(';'));
+
+        match prefix {
+            Some(prefix) => {
+                self.namespaces.insert(prefix, url);
+            },
+            None => self.default_namespace = Some(url)
+        }
+    }
+
+    /// Parse a url, either quoted or in `url()`
+    fn parse_url (&mut self) -> String {
+        match self.next_char() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                self.consume_char();
+                let url = self.consume_while(|b| b != quote as u8).to_string();
+                assert!(self.consume_char() == Some(quote));
+                url
+            },
+            _ => {
+                assert!(self.parse_identifier().eq_ignore_ascii_case("url"));
+                assert!(self.consume_char() == Some('('));
+                self.consume_whitespace();
+                let url = match self.next_char() {
+                    Some('"') | Some('\'') => self.parse_url(),
+                    _ => self.consume_while(|b| b != b')' && !b.is_ascii_whitespace()).to_string()
+                };
+                self.consume_whitespace();
+                assert!(self.consume_char() == Some(')'));
+                url
+            }
+        }
+    }
+
+    fn parse_selectors (&mut self) -> Result<Vec<Selector>, SelectorError> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
+            selectors.push(Selector::Simple(self.parse_simple_selector()?));
             self.consume_whitespace();
             match self.next_char() {
-                ',' => {
+                Some(',') => {
                     self.consume_char();
                     self.consume_whitespace();
                 },
-                '{' => break,
-                c => panic!("Unexpected character {} in selector list", c)
+                Some('{') | None => break,
+                Some(c) => return Err(self.selector_error(SelectorErrorKind::UnexpectedCharacter(c)))
             }
         }
         selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        selectors
+        Ok(selectors)
     }
 
-    fn parse_simple_selector (&mut self) -> SimpleSelector {
+    fn parse_simple_selector (&mut self) -> Result<SimpleSelector, SelectorError> {
+        let start = self.pos;
         let mut selector = SimpleSelector {
+            namespace: self.default_namespace.clone(),
             tag_name: None,
             id: None,
             class: Vec::new()
         };
-        while !self.eof() {
-            match self.next_char() {
+        while let Some(c) = self.next_char() {
+            match c {
                 '#' => {
                     self.consume_char();
-                    selector.id = Some(self.parse_identifier());
+                    selector.id = Some(self.parse_selector_name()?.to_string());
                 },
                 '.' => {
                     self.consume_char();
-                    selector.class.push(self.parse_identifier());
+                    selector.class.push(self.parse_selector_name()?.to_string());
                 },
-                '*' => {
-                    self.consume_char();
+                // A type selector with an optional namespace: `*`, `name`, `prefix|name`,
+                // `*|name` for any namespace or `|name` for none, with `*` for any name
+                '*' | '|' => {
+                    let any = self.consume_char() == Some('*');
+                    if any && self.next_char() != Some('|') {
+                        continue;
+                    }
+                    if any {
+                        self.consume_char();
+                    }
+                    selector.namespace = if any { None } else { Some(Atom::from("")) };
+                    self.parse_type_name(&mut selector)?;
                 },
-                c if Parser::valid_indentifier_char(c) => {
-                    selector.tag_name = Some(self.parse_identifier());
+                c if c.is_ascii() && Parser::valid_indentifier_char(c as u8) => {
+                    let name_start = self.pos;
+                    let name = self.parse_identifier().to_string();
+                    if self.next_char() == Some('|') {
+                        selector.namespace = match self.namespaces.get(&name) {
+                            Some(url) => Some(url.clone()),
+                            None => return Err(SelectorError {
+                                kind: SelectorErrorKind::UndeclaredNamespacePrefix(name),
+                                offset: name_start
+                            })
+                        };
+                        self.consume_char();
+                        self.parse_type_name(&mut selector)?;
+                    } else {
+                        selector.tag_name = Some(Atom::from(&*name));
+                    }
                 },
                 _ => break
             }
         }
-        selector
+        if self.pos == start {
+            return Err(self.selector_error(SelectorErrorKind::EmptySelector));
+        }
+        Ok(selector)
+    }
+
+    /// Parse the element name after the `|` of a type selector, which is `*` for any name
+    fn parse_type_name (&mut self, selector: &mut SimpleSelector) -> Result<(), SelectorError> {
+        if self.next_char() == Some('*') {
+            self.consume_char();
+        } else {
+            selector.tag_name = Some(Atom::from(self.parse_selector_name()?));
+        }
+        Ok(())
+    }
+
+    /// Parse the name that has to follow a `#`, `.` or `|` in a selector
+    fn parse_selector_name (&mut self) -> Result<&str, SelectorError> {
+        match self.next_char() {
+            Some(c) if c.is_ascii() && Parser::valid_indentifier_char(c as u8) => Ok(self.parse_identifier()),
+            Some(c) => Err(self.selector_error(SelectorErrorKind::UnexpectedCharacter(c))),
+            None => Err(self.selector_error(SelectorErrorKind::UnexpectedEof))
+        }
     }
 
     fn parse_declarations (&mut self) -> Vec<Declaration> {
-        assert!(self.consume_char() == '{');
+        assert!(self.consume_char() == Some('{'));
         let mut declarations = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
+            if self.next_char() == Some('}') {
                 self.consume_char();
                 break;
             }
@@ -252,13 +324,13 @@ impl CssParser for Parser {
     }
 
     fn parse_declaration (&mut self) -> Declaration {
-        let property_name = self.parse_identifier();
+        let property_name = Atom::from(self.parse_identifier());
         self.consume_whitespace();
-        assert!(self.consume_char() == ':');
+        assert!(self.consume_char() == Some(':'));
         self.consume_whitespace();
         let value = self.parse_value();
         self.consume_whitespace();
-        assert!(self.consume_char() == ';');
+        assert!(self.consume_char() == Some(';'));
 
         Declaration {
             name: property_name,
@@ -268,9 +340,9 @@ impl CssParser for Parser {
 
     fn parse_value (&mut self) -> Value {
         match self.next_char() {
-            '0'...'9' => self.parse_length(),
-            '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier())
+            Some('0'...'9') => self.parse_length(),
+            Some('#') => self.parse_color(),
+            _ => Value::Keyword(self.parse_identifier().to_string())
         }
     }
 
@@ -279,22 +351,21 @@ impl CssParser for Parser {
     }
 
     fn parse_float (&mut self) -> f32 {
-        let s = self.consume_while(&|c| match c {
-            '0'...'9' | '.' => true,
-            _ => false
-        });
+        let s = self.consume_while(|b| matches!(b, b'0'..=b'9' | b'.'));
         s.parse().unwrap()
     }
 
     fn parse_unit (&mut self) -> Unit {
-        match self.parse_identifier().to_ascii_lowercase().borrow() {
-            "px" => Unit::Px,
-            _ => panic!("Unrecognized unit")
+        let unit = self.parse_identifier();
+        if unit.eq_ignore_ascii_case("px") {
+            Unit::Px
+        } else {
+            panic!("Unrecognized unit")
         }
     }
 
     fn parse_color (&mut self) -> Value {
-        assert!(self.consume_char() == '#');
+        assert!(self.consume_char() == Some('#'));
         Value::ColorValue(Color {
             r: self.parse_hex_pair(),
             g: self.parse_hex_pair(),
@@ -309,14 +380,11 @@ impl CssParser for Parser {
         u8::from_str_radix(s, 16).unwrap()
     }
 
-    fn parse_identifier (&mut self) -> String {
-        self.consume_while(&Parser::valid_indentifier_char)
+    fn parse_identifier (&mut self) -> &str {
+        self.consume_while(Parser::valid_indentifier_char)
     }
 
-    fn valid_indentifier_char (c: char) -> bool {
-        match c {
-            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => true,
-            _ => false
-        }
+    fn valid_indentifier_char (b: u8) -> bool {
+        matches!(b, b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_')
     }
 }
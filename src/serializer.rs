@@ -0,0 +1,213 @@
+//! Turns dom trees back into html, following
+//! https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+//! or into the tree format of the html5lib tests, to look at the structure of a tree.
+//!
+//! Attributes are written in the order of the dom, which is their source order. The tree
+//! format sorts them by name, like the html5lib tests do.
+
+use dom::{ Document, Namespace, NodeId, NodeType };
+use html;
+
+/// Elements whose text content is written as is, without escaping
+const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "style", "script", "xmp", "iframe", "noembed", "noframes", "plaintext"
+];
+
+impl Document {
+    /// The markup of a node's children
+    pub fn inner_html (&self, id: NodeId) -> String {
+        let mut result = String::new();
+        serialize_children(self, id, &mut result);
+        result
+    }
+
+    /// The markup of a node itself, including its children. Documents and fragments are
+    /// written as their children.
+    pub fn outer_html (&self, id: NodeId) -> String {
+        let mut result = String::new();
+        serialize_node(self, id, &mut result);
+        result
+    }
+}
+
+/// The markup of a whole document, with its doctype if it has one
+pub fn serialize_document (document: &Document) -> String {
+    document.inner_html(document.root())
+}
+
+/// The tree under a node in the indented format of the html5lib tree construction tests:
+///
+/// ```text
+/// | <!DOCTYPE html>
+/// | <html>
+/// |   <head>
+/// |   <body>
+/// |     <p>
+/// |       class="note"
+/// |       "Hello"
+/// ```
+///
+/// Foreign elements are prefixed with their namespace, like `<svg circle>`, and the contents
+/// of a template are written under a `content` line.
+pub fn dump_tree (document: &Document, id: NodeId) -> String {
+    let mut lines = Vec::new();
+    for child in document.children(id) {
+        dump_node(document, child, 0, &mut lines);
+    }
+    lines.iter().map(|line| format!("| {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+/// Write the lines of a node and everything below it, starting at `depth`
+fn dump_node (document: &Document, id: NodeId, depth: usize, lines: &mut Vec<String>) {
+    let mut stack = vec![(id, depth)];
+    while let Some((id, depth)) = stack.pop() {
+        let indent = "  ".repeat(depth);
+        match document[id].node_type {
+            NodeType::Element(ref element) => {
+                let prefix = match element.namespace() {
+                    Some(Namespace::Svg) => "svg ",
+                    Some(Namespace::MathMl) => "math ",
+                    _ => ""
+                };
+                lines.push(format!("{}<{}{}>", indent, prefix, element.name.local));
+                // Attributes are sorted by name as written, with a space after the prefix
+                let mut attributes: Vec<(String, &str)> = element.attributes().map(|attribute| match attribute.name.prefix {
+                    Some(ref prefix) => (format!("{} {}", prefix, attribute.name.local), &*attribute.value),
+                    None => (attribute.name.local.to_string(), &*attribute.value)
+                }).collect();
+                attributes.sort();
+                for (name, value) in attributes {
+                    lines.push(format!("{}  {}=\"{}\"", indent, name, value));
+                }
+                push_children(document, id, depth + 1, &mut stack);
+                if let Some(contents) = element.template_contents() {
+                    lines.push(format!("{}  content", indent));
+                    push_children(document, contents, depth + 2, &mut stack);
+                }
+            },
+            NodeType::Text(ref text) => lines.push(format!("{}\"{}\"", indent, text)),
+            NodeType::Comment(ref data) => lines.push(format!("{}<!-- {} -->", indent, data)),
+            NodeType::Doctype(ref doctype) => {
+                let mut line = format!("{}<!DOCTYPE {}", indent, doctype.name);
+                if doctype.public_id.is_some() || doctype.system_id.is_some() {
+                    line.push_str(&format!(" \"{}\" \"{}\"",
+                                           doctype.public_id.as_ref().map_or("", |id| &**id),
+                                           doctype.system_id.as_ref().map_or("", |id| &**id)));
+                }
+                line.push('>');
+                lines.push(line);
+            },
+            NodeType::Document | NodeType::DocumentFragment => push_children(document, id, depth, &mut stack)
+        }
+    }
+}
+
+/// Put the children of a node on a stack of nodes to visit, so that the first child is on top
+fn push_children<T: Copy> (document: &Document, id: NodeId, with: T, stack: &mut Vec<(NodeId, T)>) {
+    let mut child = document[id].last_child();
+    while let Some(node) = child {
+        stack.push((node, with));
+        child = document[node].previous_sibling();
+    }
+}
+
+fn serialize_children (document: &Document, id: NodeId, result: &mut String) {
+    let mut stack = Vec::new();
+    push_serialized_children(document, id, &mut stack);
+    serialize(document, stack, result);
+}
+
+fn serialize_node (document: &Document, id: NodeId, result: &mut String) {
+    serialize(document, vec![(id, Serialize::Node(None))], result);
+}
+
+/// What is left to write for a node: all of it, with the name of its parent if that is an
+/// html element, or the closing tag once its children are done
+#[derive(Clone, Copy)]
+enum Serialize<'a> {
+    Node(Option<&'a str>),
+    ClosingTag
+}
+
+fn push_serialized_children<'a> (document: &'a Document, id: NodeId, stack: &mut Vec<(NodeId, Serialize<'a>)>) {
+    let parent = match document[id].node_type {
+        NodeType::Element(ref element) if !element.is_foreign() => Some(&*element.name.local),
+        _ => None
+    };
+    // The markup of a template is its contents
+    let id = match document[id].node_type {
+        NodeType::Element(ref element) => element.template_contents().unwrap_or(id),
+        _ => id
+    };
+    push_children(document, id, Serialize::Node(parent), stack);
+}
+
+/// Write the nodes on the stack, top first, going through the tree without recursion so that
+/// deeply nested documents can't overflow the call stack
+fn serialize<'a> (document: &'a Document, mut stack: Vec<(NodeId, Serialize<'a>)>, result: &mut String) {
+    while let Some((id, step)) = stack.pop() {
+        let parent = match step {
+            Serialize::Node(parent) => parent,
+            Serialize::ClosingTag => {
+                if let NodeType::Element(ref element) = document[id].node_type {
+                    result.push_str("</");
+                    result.push_str(&element.name.local);
+                    result.push('>');
+                }
+                continue;
+            }
+        };
+        match document[id].node_type {
+            NodeType::Element(ref element) => {
+                result.push('<');
+                result.push_str(&element.name.local);
+                for attribute in element.attributes() {
+                    result.push(' ');
+                    result.push_str(&attribute.name.to_string());
+                    result.push_str("=\"");
+                    escape(&attribute.value, true, result);
+                    result.push('"');
+                }
+                result.push('>');
+
+                if !element.is_foreign() && html::is_void_element(&element.name.local) {
+                    continue;
+                }
+                stack.push((id, Serialize::ClosingTag));
+                push_serialized_children(document, id, &mut stack);
+            },
+            NodeType::Text(ref text) => {
+                if parent.is_some_and(|parent| RAW_TEXT_ELEMENTS.contains(&parent)) {
+                    result.push_str(text);
+                } else {
+                    escape(text, false, result);
+                }
+            },
+            NodeType::Comment(ref data) => {
+                result.push_str("<!--");
+                result.push_str(data);
+                result.push_str("-->");
+            },
+            NodeType::Doctype(ref doctype) => {
+                result.push_str("<!DOCTYPE ");
+                result.push_str(&doctype.name);
+                result.push('>');
+            },
+            NodeType::Document | NodeType::DocumentFragment => push_serialized_children(document, id, &mut stack)
+        }
+    }
+}
+
+/// Escape text, or an attribute value if `in_attribute` is set
+fn escape (text: &str, in_attribute: bool, result: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '\u{a0}' => result.push_str("&nbsp;"),
+            '"' if in_attribute => result.push_str("&quot;"),
+            '<' if !in_attribute => result.push_str("&lt;"),
+            '>' if !in_attribute => result.push_str("&gt;"),
+            c => result.push(c)
+        }
+    }
+}
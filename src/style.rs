@@ -6,30 +6,48 @@
 
 use std::collections::{ HashMap };
 use css::{ Stylesheet, Selector, SimpleSelector, Value, Specificity, Rule };
-use dom::{ ElementData, Node, NodeType };
+use parser::{ self, SelectorError };
+use dom::{ Document, ElementData, Node, NodeId, NodeType };
+use atom::Atom;
 
-pub type PropertyMap = HashMap<String, Value>;
+pub type PropertyMap = HashMap<Atom, Value>;
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
+/// Properties whose value is passed from elements to their children, if the children don't
+/// have their own value
+const INHERITED_PROPERTIES: &[&str] = &["white-space"];
+
 #[derive(Debug)]
 pub struct StyledNode<'a> {
-    node: &'a Node,
+    pub id: NodeId,
+    pub node: &'a Node,
     pub specified_values: PropertyMap,
     pub children: Vec<StyledNode<'a>>
 }
 
-fn matches (elem: &ElementData, selector: &Selector) -> bool {
+/// Returns true if the element matches the selector
+pub fn matches (elem: &ElementData, selector: &Selector) -> bool {
     match *selector {
         Selector::Simple(ref simple_selector) => matches_simpe_selector(elem, &simple_selector)
     }
 }
 
 fn matches_simpe_selector (elem: &ElementData, selector: &SimpleSelector) -> bool {
-    if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
+    if selector.namespace.iter().any(|namespace| elem.name.ns != *namespace) {
         return false;
     }
 
-    if selector.id.iter().any(|id| elem.id() != Some(id)) {
+    // Html names are case-insensitive, but foreign ones like `linearGradient` are not
+    let name_matches = |name: &Atom| if elem.is_foreign() {
+        elem.name.local == *name
+    } else {
+        elem.name.local.eq_ignore_ascii_case(name)
+    };
+    if selector.tag_name.iter().any(|name| !name_matches(name)) {
+        return false;
+    }
+
+    if selector.id.iter().any(|id| elem.id() != Some(&**id)) {
         return false;
     }
 
@@ -40,6 +58,29 @@ fn matches_simpe_selector (elem: &ElementData, selector: &SimpleSelector) -> boo
     true
 }
 
+impl Document {
+    /// The first element under `scope` that matches one of the comma separated `selectors`, in
+    /// document order, or an error if the selectors can't be parsed
+    pub fn query_selector (&self, scope: NodeId, selectors: &str) -> Result<Option<NodeId>, SelectorError> {
+        let selectors = parser::parse_selector_list(selectors)?;
+        Ok(self.descendants(scope).elements().find(|&id| self.matches_any(id, &selectors)))
+    }
+
+    /// Every element under `scope` that matches one of the comma separated `selectors`, in
+    /// document order, or an error if the selectors can't be parsed
+    pub fn query_selector_all (&self, scope: NodeId, selectors: &str) -> Result<Vec<NodeId>, SelectorError> {
+        let selectors = parser::parse_selector_list(selectors)?;
+        Ok(self.descendants(scope).elements().filter(|&id| self.matches_any(id, &selectors)).collect())
+    }
+
+    fn matches_any (&self, id: NodeId, selectors: &[Selector]) -> bool {
+        match self[id].node_type {
+            NodeType::Element(ref elem) => selectors.iter().any(|selector| matches(elem, selector)),
+            _ => false
+        }
+    }
+}
+
 fn match_rule<'a> (elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
     rule.selectors.iter()
         .find(|selector| matches(elem, *selector))
@@ -63,13 +104,32 @@ fn specified_values (elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap
     values
 }
 
-pub fn style_tree<'a> (root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+/// Style the tree under `root`, usually the document element
+pub fn style_tree<'a> (document: &'a Document, root: NodeId, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+    style_node(document, root, stylesheet, &HashMap::new())
+}
+
+fn style_node<'a> (document: &'a Document, id: NodeId, stylesheet: &'a Stylesheet, parent_values: &PropertyMap) -> StyledNode<'a> {
+    let node = &document[id];
+    let mut values = match node.node_type {
+        NodeType::Element(ref elem) => specified_values(elem, stylesheet),
+        _ => HashMap::new()
+    };
+    for &name in INHERITED_PROPERTIES {
+        if !values.contains_key(name) {
+            if let Some(value) = parent_values.get(name) {
+                values.insert(Atom::from(name), value.clone());
+            }
+        }
+    }
+
     StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            NodeType::Text(_) => HashMap::new()
-        },
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect()
+        id,
+        node,
+        children: document.children(id)
+            .filter(|&child| !matches!(document[child].node_type, NodeType::Comment(_) | NodeType::Doctype(_)))
+            .map(|child| style_node(document, child, stylesheet, &values))
+            .collect(),
+        specified_values: values
     }
 }
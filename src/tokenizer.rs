@@ -0,0 +1,1595 @@
+//! The HTML tokenizer, a state machine following
+//! https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+//!
+//! Input can be added in chunks with `feed`, tokens are only returned once the input they
+//! are made of has been seen completely.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::mem;
+use dom::{ AttrMap, Span };
+use atom::Atom;
+use html::{ ParseError, ParseErrorKind };
+use entities;
+
+/// A unit of html markup, as handed from the `Tokenizer` to the tree builder. Text borrows
+/// the input where it is written as is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token<'a> {
+    Doctype {
+        name: Option<String>,
+        public_id: Option<String>,
+        system_id: Option<String>,
+        force_quirks: bool
+    },
+    StartTag {
+        name: Atom,
+        attributes: AttrMap,
+        self_closing: bool
+    },
+    EndTag {
+        name: Atom
+    },
+    Text(Cow<'a, str>),
+    Comment(String),
+    Eof
+}
+
+/// A position in the input
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    /// Byte offset into the input
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, counted in characters
+    pub column: usize
+}
+
+/// How the tokenizer scans the content of the element it is in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentModel {
+    /// Regular markup
+    Data,
+    /// Text with character references but no tags, in `title` and `textarea`
+    RcData,
+    /// Text without character references or tags, e.g. in `style`
+    RawText,
+    /// The content of `script` elements
+    ScriptData,
+    /// Everything up to the end of the input is text, after a `plaintext` tag
+    PlainText
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Data,
+    RcData,
+    RawText,
+    ScriptData,
+    PlainText,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RcDataLessThanSign,
+    RcDataEndTagOpen,
+    RcDataEndTagName,
+    RawTextLessThanSign,
+    RawTextEndTagOpen,
+    RawTextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+    Finished
+}
+
+/// The next input character, if it is available yet
+enum Input {
+    Char(char),
+    Eof,
+    /// More input has to be fed before the tokenizer can continue
+    Pending
+}
+
+#[derive(Debug, Default)]
+struct TagBuilder {
+    name: String,
+    end: bool,
+    self_closing: bool,
+    attributes: AttrMap,
+    has_attributes: bool,
+    attr_name: String,
+    attr_value: String
+}
+
+#[derive(Debug, Default)]
+struct DoctypeBuilder {
+    name: Option<String>,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    force_quirks: bool
+}
+
+/// A token waiting to be returned
+#[derive(Debug)]
+enum Queued {
+    Token(Token<'static>),
+    /// Text, or `None` while the text is the input of its span as is
+    Text(Option<String>)
+}
+
+fn is_whitespace (c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0c' | ' ')
+}
+
+#[derive(Debug)]
+pub struct Tokenizer {
+    input: String,
+    end_of_input: bool,
+    position: Position,
+    previous: Position,
+    state: State,
+    return_state: State,
+    tokens: VecDeque<(Queued, Span)>,
+    token_start: Position,
+    tag: TagBuilder,
+    comment: String,
+    doctype: DoctypeBuilder,
+    temp: String,
+    /// The `&` of the character reference being read
+    reference_start: Position,
+    character_reference_code: u32,
+    last_start_tag: Atom,
+    allow_cdata: bool,
+    pub errors: Vec<ParseError>
+}
+
+impl Default for Tokenizer {
+    fn default () -> Tokenizer {
+        Tokenizer::new()
+    }
+}
+
+impl Tokenizer {
+    /// Create a tokenizer without any input yet
+    pub fn new () -> Tokenizer {
+        let start = Position { offset: 0, line: 1, column: 1 };
+        Tokenizer {
+            input: String::new(),
+            end_of_input: false,
+            position: start,
+            previous: start,
+            state: State::Data,
+            return_state: State::Data,
+            tokens: VecDeque::new(),
+            token_start: start,
+            tag: TagBuilder::default(),
+            comment: String::new(),
+            doctype: DoctypeBuilder::default(),
+            temp: String::new(),
+            reference_start: start,
+            character_reference_code: 0,
+            last_start_tag: Atom::from(""),
+            allow_cdata: false,
+            errors: Vec::new()
+        }
+    }
+
+    /// Create a tokenizer for a complete document
+    pub fn from_source (source: &str) -> Tokenizer {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.feed(source);
+        tokenizer.end();
+        tokenizer
+    }
+
+    /// Add more input
+    pub fn feed (&mut self, input: &str) {
+        self.input.push_str(input);
+    }
+
+    /// All the input fed so far, which the spans of tokens and the offsets of errors refer to
+    pub fn into_input (self) -> String {
+        self.input
+    }
+
+    /// Mark the end of the input, after which no more input can be fed
+    pub fn end (&mut self) {
+        self.end_of_input = true;
+    }
+
+    /// Switch how the content following the last start tag is scanned
+    pub fn set_content_model (&mut self, content_model: ContentModel) {
+        self.state = match content_model {
+            ContentModel::Data => State::Data,
+            ContentModel::RcData => State::RcData,
+            ContentModel::RawText => State::RawText,
+            ContentModel::ScriptData => State::ScriptData,
+            ContentModel::PlainText => State::PlainText
+        };
+    }
+
+    /// Set the tag name end tags are matched against in raw text and RCDATA content
+    pub fn set_last_start_tag (&mut self, name: &str) {
+        self.last_start_tag = Atom::from(name);
+    }
+
+    /// Allow `<![CDATA[...]]>` sections, which only exist in svg and math content
+    pub fn set_allow_cdata (&mut self, allow_cdata: bool) {
+        self.allow_cdata = allow_cdata;
+    }
+
+    /// Returns the next token and the part of the input it was made of, or `None` if more input
+    /// is needed first. The last token is always `Token::Eof`.
+    pub fn next_token (&mut self) -> Option<(Token<'_>, Span)> {
+        // Keep going while only text has been found, so that runs of text come as one token
+        loop {
+            let only_text = match self.tokens.front() {
+                None => true,
+                Some(&(Queued::Text(_), _)) => self.tokens.len() == 1,
+                Some(_) => false
+            };
+            if !only_text || self.state == State::Finished || !self.step() {
+                break;
+            }
+        }
+
+        // Text at the end of the available input may continue in the next chunk
+        if let Some(&(Queued::Text(_), _)) = self.tokens.front() {
+            if self.tokens.len() == 1 && self.state != State::Finished && !self.end_of_input {
+                return None;
+            }
+        }
+        let (token, span) = self.tokens.pop_front()?;
+        let token = match token {
+            Queued::Token(token) => token,
+            Queued::Text(Some(text)) => Token::Text(Cow::Owned(text)),
+            Queued::Text(None) => Token::Text(Cow::Borrowed(&self.input[span.start..span.end]))
+        };
+        Some((token, span))
+    }
+
+    // Reading input
+
+    fn consume (&mut self) -> Input {
+        // Most input is ASCII, which does not need to be decoded
+        match self.input.as_bytes().get(self.position.offset) {
+            Some(&b) if b.is_ascii() && b != b'\r' => {
+                self.previous = self.position;
+                self.position.offset += 1;
+                if b == b'\n' {
+                    self.position.line += 1;
+                    self.position.column = 1;
+                } else {
+                    self.position.column += 1;
+                }
+                return Input::Char(b as char);
+            },
+            _ => {}
+        }
+
+        let mut chars = self.input[self.position.offset..].chars();
+        let c = match chars.next() {
+            Some(c) => c,
+            None if self.end_of_input => {
+                // Reconsuming the end of the input stays at the end
+                self.previous = self.position;
+                return Input::Eof;
+            },
+            None => return Input::Pending
+        };
+
+        // Carriage returns are normalized to newlines, `\r\n` counts as one newline
+        let mut length = c.len_utf8();
+        let c = if c == '\r' {
+            match chars.next() {
+                Some('\n') => length += 1,
+                Some(_) => {},
+                None if self.end_of_input => {},
+                None => return Input::Pending
+            }
+            '\n'
+        } else {
+            c
+        };
+
+        self.previous = self.position;
+        self.position.offset += length;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Input::Char(c)
+    }
+
+    /// Consume and emit the text up to the next byte in `special`, which are all ASCII.
+    /// Returns false if there is no such text at the current position.
+    fn consume_text_run (&mut self, special: &[u8]) -> bool {
+        let start = self.position;
+        let rest = &self.input.as_bytes()[start.offset..];
+        let length = rest.iter().position(|b| special.contains(b)).unwrap_or(rest.len());
+        if length == 0 {
+            return false;
+        }
+
+        let text = &self.input[start.offset..start.offset + length];
+        let mut position = start;
+        position.offset += length;
+        match text.rfind('\n') {
+            Some(newline) => {
+                position.line += text.bytes().filter(|&b| b == b'\n').count();
+                position.column = 1 + text[newline + 1..].chars().count();
+            },
+            None => position.column += text.chars().count()
+        }
+        self.previous = start;
+        self.position = position;
+
+        let input = &self.input;
+        if let Some(&mut (Queued::Text(ref mut existing), ref mut span)) = self.tokens.back_mut() {
+            // Text that has been read as is so far only needs its span extended
+            if existing.is_some() || span.end != start.offset {
+                existing.get_or_insert_with(|| input[span.start..span.end].to_string()).push_str(text);
+            }
+            span.end = position.offset;
+            return true;
+        }
+        let span = Span { start: start.offset, end: position.offset, line: start.line, column: start.column };
+        self.tokens.push_back((Queued::Text(None), span));
+        true
+    }
+
+    /// Go back to before the last consumed character, to process it again in another state
+    fn reconsume (&mut self, state: State) {
+        self.position = self.previous;
+        self.state = state;
+    }
+
+    /// Returns whether the input at the current position starts with `s`, or `None` if that is
+    /// not known before more input arrives
+    fn lookahead (&self, s: &str, ignore_case: bool) -> Option<bool> {
+        let rest = &self.input[self.position.offset..];
+        match rest.get(..s.len()) {
+            Some(start) if ignore_case => Some(start.eq_ignore_ascii_case(s)),
+            Some(start) => Some(start == s),
+            None if self.end_of_input || !s.starts_with(rest) => Some(false),
+            None => None
+        }
+    }
+
+    /// Consume `count` ASCII characters that were looked at with `lookahead`
+    fn skip (&mut self, count: usize) {
+        self.position.offset += count;
+        self.position.column += count;
+    }
+
+    // Emitting tokens
+
+    fn error (&mut self, kind: ParseErrorKind) {
+        let position = self.previous;
+        self.error_at(kind, position);
+    }
+
+    fn error_at (&mut self, kind: ParseErrorKind, position: Position) {
+        self.errors.push(ParseError {
+            kind,
+            offset: position.offset,
+            line: position.line,
+            column: position.column
+        });
+    }
+
+    /// Queue a token that started at `start` and ends at the current position
+    fn emit (&mut self, token: Token<'static>, start: Position) {
+        self.queue(Queued::Token(token), start);
+    }
+
+    fn queue (&mut self, token: Queued, start: Position) {
+        let span = Span {
+            start: start.offset,
+            end: self.position.offset,
+            line: start.line,
+            column: start.column
+        };
+        self.tokens.push_back((token, span));
+    }
+
+    fn emit_char (&mut self, c: char) {
+        let start = self.previous;
+        self.emit_char_at(c, start);
+    }
+
+    /// Emit a character, which starts a new text token at `start` unless it continues one
+    fn emit_char_at (&mut self, c: char, start: Position) {
+        // The character is the input as is unless it was a reference, a normalized newline or
+        // a replaced null
+        let input = &self.input;
+        let as_is = input[start.offset..self.position.offset] == *c.encode_utf8(&mut [0; 4]);
+        if let Some(&mut (Queued::Text(ref mut text), ref mut span)) = self.tokens.back_mut() {
+            if text.is_some() || !as_is || span.end != start.offset {
+                text.get_or_insert_with(|| input[span.start..span.end].to_string()).push(c);
+            }
+            span.end = self.position.offset;
+            return;
+        }
+        let text = if as_is { None } else { Some(c.to_string()) };
+        self.queue(Queued::Text(text), start);
+    }
+
+    fn emit_str (&mut self, s: &str) {
+        for c in s.chars() {
+            self.emit_char(c);
+        }
+    }
+
+    fn emit_eof (&mut self) {
+        let position = self.position;
+        self.emit(Token::Eof, position);
+        self.state = State::Finished;
+    }
+
+    fn start_tag (&mut self, end: bool) {
+        self.tag = TagBuilder { end, ..TagBuilder::default() };
+        // The tag started at the `<` before the characters consumed so far
+        self.token_start = self.tag_open_position();
+    }
+
+    /// The position of the `<` of the tag being opened
+    fn tag_open_position (&self) -> Position {
+        let back = if self.state == State::TagOpen { 1 } else { 2 };
+        Position {
+            offset: self.previous.offset - back,
+            line: self.previous.line,
+            column: self.previous.column.saturating_sub(back)
+        }
+    }
+
+    fn start_attribute (&mut self) {
+        self.finish_attribute();
+        self.tag.has_attributes = true;
+    }
+
+    fn finish_attribute (&mut self) {
+        if self.tag.attr_name.is_empty() && !self.tag.has_attributes {
+            return;
+        }
+        let name = Atom::from(&*self.tag.attr_name);
+        self.tag.attr_name.clear();
+        let value = mem::take(&mut self.tag.attr_value);
+        // Only the first of attributes with the same name is kept
+        if self.tag.attributes.iter().any(|(existing, _)| *existing == name) {
+            self.error(ParseErrorKind::DuplicateAttribute(name.to_string()));
+        } else {
+            self.tag.attributes.push((name, value));
+        }
+        self.tag.has_attributes = false;
+    }
+
+    fn emit_tag (&mut self) {
+        self.finish_attribute();
+        let tag = mem::take(&mut self.tag);
+        let start = self.token_start;
+        if tag.end {
+            if !tag.attributes.is_empty() {
+                self.error(ParseErrorKind::EndTagWithAttributes);
+            }
+            if tag.self_closing {
+                self.error(ParseErrorKind::EndTagWithTrailingSolidus);
+            }
+            self.emit(Token::EndTag { name: Atom::from(tag.name) }, start);
+        } else {
+            let name = Atom::from(tag.name);
+            self.last_start_tag = name.clone();
+            self.emit(Token::StartTag { name, attributes: tag.attributes, self_closing: tag.self_closing }, start);
+        }
+    }
+
+    fn is_appropriate_end_tag (&self) -> bool {
+        self.tag.end && self.last_start_tag == *self.tag.name
+    }
+
+    fn start_comment (&mut self, data: &str, start: Position) {
+        self.comment = data.to_string();
+        self.token_start = start;
+    }
+
+    fn emit_comment (&mut self) {
+        let data = mem::take(&mut self.comment);
+        let start = self.token_start;
+        self.emit(Token::Comment(data), start);
+    }
+
+    fn emit_doctype (&mut self) {
+        let doctype = mem::take(&mut self.doctype);
+        let start = self.token_start;
+        self.emit(Token::Doctype {
+            name: doctype.name,
+            public_id: doctype.public_id,
+            system_id: doctype.system_id,
+            force_quirks: doctype.force_quirks
+        }, start);
+    }
+
+    fn doctype_eof (&mut self) {
+        self.error(ParseErrorKind::UnexpectedEof);
+        self.doctype.force_quirks = true;
+        self.emit_doctype();
+        self.emit_eof();
+    }
+
+    fn in_attribute_value (&self) -> bool {
+        matches!(self.return_state,
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted | State::AttributeValueUnquoted)
+    }
+
+    /// Read a character reference after the `&` that was just consumed, then continue in
+    /// `return_state`
+    fn start_character_reference (&mut self, return_state: State) {
+        self.return_state = return_state;
+        self.state = State::CharacterReference;
+        self.reference_start = self.previous;
+    }
+
+    /// An error in the character reference being read, reported at its `&`
+    fn character_reference_error (&mut self, kind: ParseErrorKind) {
+        let start = self.reference_start;
+        self.error_at(kind, start);
+    }
+
+    /// Hand the characters of a character reference to the attribute value or the text, where
+    /// they start at the `&`
+    fn flush_character_reference (&mut self) {
+        let temp = mem::take(&mut self.temp);
+        if self.in_attribute_value() {
+            self.tag.attr_value.push_str(&temp);
+        } else {
+            let start = self.reference_start;
+            for c in temp.chars() {
+                self.emit_char_at(c, start);
+            }
+        }
+    }
+
+    /// Emit `</` and the buffered tag name as text, when they turned out not to be an end tag
+    fn emit_end_tag_open_as_text (&mut self) {
+        self.emit_str("</");
+        let temp = mem::take(&mut self.temp);
+        self.emit_str(&temp);
+    }
+
+    // The state machine
+
+    /// Run the state machine for one character. Returns false if more input is needed.
+    fn step (&mut self) -> bool {
+        match self.state {
+            State::NamedCharacterReference => return self.named_character_reference(),
+            State::MarkupDeclarationOpen => return self.markup_declaration_open(),
+            State::NumericCharacterReferenceEnd => {
+                self.numeric_character_reference_end();
+                return true;
+            },
+            State::AfterDoctypeName => return self.after_doctype_name(),
+            State::Finished => return false,
+            _ => {}
+        }
+
+        // Plain text is taken in one go instead of character by character
+        let special: &[u8] = match self.state {
+            State::Data | State::RcData => b"&<\0\r",
+            State::RawText | State::ScriptData => b"<\0\r",
+            State::PlainText => b"\0\r",
+            _ => b""
+        };
+        if !special.is_empty() && self.consume_text_run(special) {
+            return true;
+        }
+
+        let c = match self.consume() {
+            Input::Char(c) => Some(c),
+            Input::Eof => None,
+            Input::Pending => return false
+        };
+
+        match self.state {
+            State::Data => match c {
+                Some('&') => {
+                    self.start_character_reference(State::Data);
+                },
+                Some('<') => self.state = State::TagOpen,
+                Some('\0') => {
+                    self.error(ParseErrorKind::UnexpectedNullCharacter);
+                    self.emit_char('\0');
+                },
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof()
+            },
+            State::RcData => match c {
+                Some('&') => {
+                    self.start_character_reference(State::RcData);
+                },
+                Some('<') => self.state = State::RcDataLessThanSign,
+                Some(c) => self.emit_text_char(c),
+                None => self.emit_eof()
+            },
+            State::RawText => match c {
+                Some('<') => self.state = State::RawTextLessThanSign,
+                Some(c) => self.emit_text_char(c),
+                None => self.emit_eof()
+            },
+            State::ScriptData => match c {
+                Some('<') => self.state = State::ScriptDataLessThanSign,
+                Some(c) => self.emit_text_char(c),
+                None => self.emit_eof()
+            },
+            State::PlainText => match c {
+                Some(c) => self.emit_text_char(c),
+                None => self.emit_eof()
+            },
+            State::TagOpen => match c {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(false);
+                    self.reconsume(State::TagName);
+                },
+                Some('?') => {
+                    self.error(ParseErrorKind::BogusComment);
+                    let start = self.tag_open_position();
+                    self.start_comment("", start);
+                    self.reconsume(State::BogusComment);
+                },
+                Some(_) => {
+                    self.error(ParseErrorKind::MissingTagName);
+                    let start = self.tag_open_position();
+                    self.emit_char_at('<', start);
+                    self.reconsume(State::Data);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_char('<');
+                    self.emit_eof();
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(true);
+                    self.reconsume(State::TagName);
+                },
+                Some('>') => {
+                    self.error(ParseErrorKind::MissingTagName);
+                    self.state = State::Data;
+                },
+                Some(_) => {
+                    self.error(ParseErrorKind::BogusComment);
+                    let start = self.tag_open_position();
+                    self.start_comment("", start);
+                    self.reconsume(State::BogusComment);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_str("</");
+                    self.emit_eof();
+                }
+            },
+            State::TagName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                },
+                Some('\0') => {
+                    self.error(ParseErrorKind::UnexpectedNullCharacter);
+                    self.tag.name.push('\u{fffd}');
+                },
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::RcDataLessThanSign => self.text_less_than_sign(c, State::RcData, State::RcDataEndTagOpen),
+            State::RcDataEndTagOpen => self.text_end_tag_open(c, State::RcData, State::RcDataEndTagName),
+            State::RcDataEndTagName => self.text_end_tag_name(c, State::RcData),
+            State::RawTextLessThanSign => self.text_less_than_sign(c, State::RawText, State::RawTextEndTagOpen),
+            State::RawTextEndTagOpen => self.text_end_tag_open(c, State::RawText, State::RawTextEndTagName),
+            State::RawTextEndTagName => self.text_end_tag_name(c, State::RawText),
+            State::ScriptDataLessThanSign => match c {
+                Some('!') => {
+                    self.state = State::ScriptDataEscapeStart;
+                    self.emit_str("<!");
+                },
+                c => self.text_less_than_sign(c, State::ScriptData, State::ScriptDataEndTagOpen)
+            },
+            State::ScriptDataEndTagOpen => self.text_end_tag_open(c, State::ScriptData, State::ScriptDataEndTagName),
+            State::ScriptDataEndTagName => self.text_end_tag_name(c, State::ScriptData),
+            State::ScriptDataEscapeStart => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapeStartDash;
+                    self.emit_char('-');
+                },
+                _ => self.reconsume(State::ScriptData)
+            },
+            State::ScriptDataEscapeStartDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                },
+                _ => self.reconsume(State::ScriptData)
+            },
+            State::ScriptDataEscaped => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDash;
+                    self.emit_char('-');
+                },
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some(c) => self.emit_text_char(c),
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDash | State::ScriptDataEscapedDashDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                },
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('>') if self.state == State::ScriptDataEscapedDashDash => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                },
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_text_char(c);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temp.clear();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                },
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp.clear();
+                    self.emit_char('<');
+                    self.reconsume(State::ScriptDataDoubleEscapeStart);
+                },
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume(State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagOpen => self.text_end_tag_open(c, State::ScriptDataEscaped, State::ScriptDataEscapedEndTagName),
+            State::ScriptDataEscapedEndTagName => self.text_end_tag_name(c, State::ScriptDataEscaped),
+            State::ScriptDataDoubleEscapeStart => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    self.state = if self.temp == "script" { State::ScriptDataDoubleEscaped } else { State::ScriptDataEscaped };
+                    self.emit_char(c);
+                },
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp.push(c.to_ascii_lowercase());
+                    self.emit_char(c);
+                },
+                _ => self.reconsume(State::ScriptDataEscaped)
+            },
+            State::ScriptDataDoubleEscaped | State::ScriptDataDoubleEscapedDash | State::ScriptDataDoubleEscapedDashDash => match c {
+                Some('-') => {
+                    self.state = match self.state {
+                        State::ScriptDataDoubleEscaped => State::ScriptDataDoubleEscapedDash,
+                        _ => State::ScriptDataDoubleEscapedDashDash
+                    };
+                    self.emit_char('-');
+                },
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                },
+                Some('>') if self.state == State::ScriptDataDoubleEscapedDashDash => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                },
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_text_char(c);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temp.clear();
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                    self.emit_char('/');
+                },
+                _ => self.reconsume(State::ScriptDataDoubleEscaped)
+            },
+            State::ScriptDataDoubleEscapeEnd => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    self.state = if self.temp == "script" { State::ScriptDataEscaped } else { State::ScriptDataDoubleEscaped };
+                    self.emit_char(c);
+                },
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp.push(c.to_ascii_lowercase());
+                    self.emit_char(c);
+                },
+                _ => self.reconsume(State::ScriptDataDoubleEscaped)
+            },
+            State::BeforeAttributeName => match c {
+                Some(c) if is_whitespace(c) => {},
+                Some('/') | Some('>') | None => self.reconsume(State::AfterAttributeName),
+                Some('=') => {
+                    self.error(ParseErrorKind::UnexpectedCharacter('='));
+                    self.start_attribute();
+                    self.tag.attr_name.push('=');
+                    self.state = State::AttributeName;
+                },
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume(State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => self.reconsume(State::AfterAttributeName),
+                None => self.reconsume(State::AfterAttributeName),
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => {
+                    self.error(ParseErrorKind::UnexpectedNullCharacter);
+                    self.tag.attr_name.push('\u{fffd}');
+                },
+                Some(c) => {
+                    if c == '"' || c == '\'' || c == '<' {
+                        self.error(ParseErrorKind::UnexpectedCharacter(c));
+                    }
+                    self.tag.attr_name.push(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match c {
+                Some(c) if is_whitespace(c) => {},
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                },
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume(State::AttributeName);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::BeforeAttributeValue => match c {
+                Some(c) if is_whitespace(c) => {},
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    let name = self.tag.attr_name.clone();
+                    self.error(ParseErrorKind::MissingAttributeValue(name));
+                    self.state = State::Data;
+                    self.emit_tag();
+                },
+                _ => self.reconsume(State::AttributeValueUnquoted)
+            },
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote = if self.state == State::AttributeValueDoubleQuoted { '"' } else { '\'' };
+                match c {
+                    Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+                    Some('&') => {
+                        let state = self.state;
+                        self.start_character_reference(state);
+                    },
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.tag.attr_value.push('\u{fffd}');
+                    },
+                    Some(c) => self.tag.attr_value.push(c),
+                    None => {
+                        self.error(ParseErrorKind::UnexpectedEof);
+                        self.emit_eof();
+                    }
+                }
+            },
+            State::AttributeValueUnquoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('&') => {
+                    self.start_character_reference(State::AttributeValueUnquoted);
+                },
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                },
+                Some('\0') => {
+                    self.error(ParseErrorKind::UnexpectedNullCharacter);
+                    self.tag.attr_value.push('\u{fffd}');
+                },
+                Some(c) => {
+                    if "\"'<=`".contains(c) {
+                        self.error(ParseErrorKind::UnexpectedCharacter(c));
+                    }
+                    self.tag.attr_value.push(c);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                },
+                Some(c) => {
+                    self.error(ParseErrorKind::UnexpectedCharacter(c));
+                    self.reconsume(State::BeforeAttributeName);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag();
+                },
+                Some(_) => {
+                    self.error(ParseErrorKind::UnexpectedCharacter('/'));
+                    self.reconsume(State::BeforeAttributeName);
+                },
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                },
+                Some('\0') => {
+                    self.error(ParseErrorKind::UnexpectedNullCharacter);
+                    self.comment.push('\u{fffd}');
+                },
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                    self.state = State::Data;
+                    self.emit_comment();
+                },
+                _ => self.reconsume(State::Comment)
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                    self.state = State::Data;
+                    self.emit_comment();
+                },
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(State::Comment);
+                },
+                None => self.comment_eof()
+            },
+            State::Comment => match c {
+                Some('<') => {
+                    self.comment.push('<');
+                    self.state = State::CommentLessThanSign;
+                },
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => {
+                    self.error(ParseErrorKind::UnexpectedNullCharacter);
+                    self.comment.push('\u{fffd}');
+                },
+                Some(c) => self.comment.push(c),
+                None => self.comment_eof()
+            },
+            State::CommentLessThanSign => match c {
+                Some('!') => {
+                    self.comment.push('!');
+                    self.state = State::CommentLessThanSignBang;
+                },
+                Some('<') => self.comment.push('<'),
+                _ => self.reconsume(State::Comment)
+            },
+            State::CommentLessThanSignBang => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDash,
+                _ => self.reconsume(State::Comment)
+            },
+            State::CommentLessThanSignBangDash => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDashDash,
+                _ => self.reconsume(State::CommentEndDash)
+            },
+            State::CommentLessThanSignBangDashDash => match c {
+                Some('>') | None => self.reconsume(State::CommentEnd),
+                Some(_) => {
+                    self.error(ParseErrorKind::NestedComment);
+                    self.reconsume(State::CommentEnd);
+                }
+            },
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(State::Comment);
+                },
+                None => self.comment_eof()
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                },
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume(State::Comment);
+                },
+                None => self.comment_eof()
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                },
+                Some('>') => {
+                    self.error(ParseErrorKind::IncorrectlyClosedComment);
+                    self.state = State::Data;
+                    self.emit_comment();
+                },
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume(State::Comment);
+                },
+                None => self.comment_eof()
+            },
+            State::Doctype => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeDoctypeName,
+                Some('>') => self.reconsume(State::BeforeDoctypeName),
+                Some(_) => {
+                    self.error(ParseErrorKind::MalformedDoctype);
+                    self.reconsume(State::BeforeDoctypeName);
+                },
+                None => self.doctype_eof()
+            },
+            State::BeforeDoctypeName => match c {
+                Some(c) if is_whitespace(c) => {},
+                Some('>') => {
+                    self.error(ParseErrorKind::MalformedDoctype);
+                    self.doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_doctype();
+                },
+                Some(c) => {
+                    let c = if c == '\0' {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        '\u{fffd}'
+                    } else {
+                        c.to_ascii_lowercase()
+                    };
+                    self.doctype.name = Some(c.to_string());
+                    self.state = State::DoctypeName;
+                },
+                None => self.doctype_eof()
+            },
+            State::DoctypeName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                },
+                Some(c) => {
+                    let c = if c == '\0' {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        '\u{fffd}'
+                    } else {
+                        c.to_ascii_lowercase()
+                    };
+                    self.doctype.name.get_or_insert_with(String::new).push(c);
+                },
+                None => self.doctype_eof()
+            },
+            State::AfterDoctypePublicKeyword | State::AfterDoctypeSystemKeyword => {
+                let public = self.state == State::AfterDoctypePublicKeyword;
+                match c {
+                    Some(c) if is_whitespace(c) => {
+                        self.state = if public { State::BeforeDoctypePublicIdentifier } else { State::BeforeDoctypeSystemIdentifier };
+                    },
+                    Some(quote) if quote == '"' || quote == '\'' => {
+                        self.error(ParseErrorKind::MalformedDoctype);
+                        self.start_doctype_identifier(public, quote);
+                    },
+                    c => self.malformed_doctype(c)
+                }
+            },
+            State::BeforeDoctypePublicIdentifier | State::BeforeDoctypeSystemIdentifier => {
+                let public = self.state == State::BeforeDoctypePublicIdentifier;
+                match c {
+                    Some(c) if is_whitespace(c) => {},
+                    Some(quote) if quote == '"' || quote == '\'' => self.start_doctype_identifier(public, quote),
+                    c => self.malformed_doctype(c)
+                }
+            },
+            State::DoctypePublicIdentifierDoubleQuoted | State::DoctypePublicIdentifierSingleQuoted
+                    | State::DoctypeSystemIdentifierDoubleQuoted | State::DoctypeSystemIdentifierSingleQuoted => {
+                let (public, quote) = match self.state {
+                    State::DoctypePublicIdentifierDoubleQuoted => (true, '"'),
+                    State::DoctypePublicIdentifierSingleQuoted => (true, '\''),
+                    State::DoctypeSystemIdentifierDoubleQuoted => (false, '"'),
+                    _ => (false, '\'')
+                };
+                match c {
+                    Some(c) if c == quote => {
+                        self.state = if public { State::AfterDoctypePublicIdentifier } else { State::AfterDoctypeSystemIdentifier };
+                    },
+                    Some('>') => {
+                        self.error(ParseErrorKind::MalformedDoctype);
+                        self.doctype.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_doctype();
+                    },
+                    Some(c) => {
+                        let c = if c == '\0' {
+                            self.error(ParseErrorKind::UnexpectedNullCharacter);
+                            '\u{fffd}'
+                        } else {
+                            c
+                        };
+                        let identifier = if public { &mut self.doctype.public_id } else { &mut self.doctype.system_id };
+                        identifier.get_or_insert_with(String::new).push(c);
+                    },
+                    None => self.doctype_eof()
+                }
+            },
+            State::AfterDoctypePublicIdentifier | State::BetweenDoctypePublicAndSystemIdentifiers => {
+                let between = self.state == State::BetweenDoctypePublicAndSystemIdentifiers;
+                match c {
+                    Some(c) if is_whitespace(c) => self.state = State::BetweenDoctypePublicAndSystemIdentifiers,
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_doctype();
+                    },
+                    Some(quote) if quote == '"' || quote == '\'' => {
+                        if !between {
+                            self.error(ParseErrorKind::MalformedDoctype);
+                        }
+                        self.start_doctype_identifier(false, quote);
+                    },
+                    c => self.malformed_doctype(c)
+                }
+            },
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some(c) if is_whitespace(c) => {},
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                },
+                Some(_) => {
+                    // Unlike other malformed doctypes this one does not force quirks mode
+                    self.error(ParseErrorKind::MalformedDoctype);
+                    self.reconsume(State::BogusDoctype);
+                },
+                None => self.doctype_eof()
+            },
+            State::BogusDoctype => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                },
+                Some('\0') => self.error(ParseErrorKind::UnexpectedNullCharacter),
+                Some(_) => {},
+                None => {
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+            },
+            State::CdataSection => match c {
+                Some(']') => self.state = State::CdataSectionBracket,
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                    self.emit_eof();
+                }
+            },
+            State::CdataSectionBracket => match c {
+                Some(']') => self.state = State::CdataSectionEnd,
+                _ => {
+                    self.emit_char(']');
+                    self.reconsume(State::CdataSection);
+                }
+            },
+            State::CdataSectionEnd => match c {
+                Some(']') => self.emit_char(']'),
+                Some('>') => self.state = State::Data,
+                _ => {
+                    self.emit_str("]]");
+                    self.reconsume(State::CdataSection);
+                }
+            },
+            State::CharacterReference => {
+                self.temp = "&".to_string();
+                match c {
+                    Some(c) if c.is_ascii_alphanumeric() => self.reconsume(State::NamedCharacterReference),
+                    Some('#') => {
+                        self.temp.push('#');
+                        self.state = State::NumericCharacterReference;
+                    },
+                    _ => {
+                        let state = self.return_state;
+                        self.reconsume(state);
+                        self.flush_character_reference();
+                    }
+                }
+            },
+            State::AmbiguousAmpersand => match c {
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    if self.in_attribute_value() {
+                        self.tag.attr_value.push(c);
+                    } else {
+                        self.emit_char(c);
+                    }
+                },
+                Some(';') => {
+                    self.character_reference_error(ParseErrorKind::UnknownNamedCharacterReference);
+                    let state = self.return_state;
+                    self.reconsume(state);
+                },
+                _ => {
+                    let state = self.return_state;
+                    self.reconsume(state);
+                }
+            },
+            State::NumericCharacterReference => {
+                self.character_reference_code = 0;
+                match c {
+                    Some(c) if c == 'x' || c == 'X' => {
+                        self.temp.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                    },
+                    _ => self.reconsume(State::DecimalCharacterReferenceStart)
+                }
+            },
+            State::HexadecimalCharacterReferenceStart | State::DecimalCharacterReferenceStart => {
+                let hex = self.state == State::HexadecimalCharacterReferenceStart;
+                match c {
+                    Some(c) if (hex && c.is_ascii_hexdigit()) || c.is_ascii_digit() => {
+                        self.reconsume(if hex { State::HexadecimalCharacterReference } else { State::DecimalCharacterReference });
+                    },
+                    _ => {
+                        self.character_reference_error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                        let state = self.return_state;
+                        self.reconsume(state);
+                        self.flush_character_reference();
+                    }
+                }
+            },
+            State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                let radix = if self.state == State::HexadecimalCharacterReference { 16 } else { 10 };
+                match c.and_then(|c| c.to_digit(radix)) {
+                    Some(digit) => {
+                        // Clamp overly long references to a value outside of the unicode range
+                        self.character_reference_code = self.character_reference_code
+                            .saturating_mul(radix)
+                            .saturating_add(digit)
+                            .min(0x11_0000);
+                    },
+                    None if c == Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                    None => {
+                        self.character_reference_error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                        self.reconsume(State::NumericCharacterReferenceEnd);
+                    }
+                }
+            },
+            State::NamedCharacterReference | State::MarkupDeclarationOpen | State::NumericCharacterReferenceEnd
+                | State::AfterDoctypeName | State::Finished => unreachable!()
+        }
+        true
+    }
+
+    /// Emit a character of text content, which never contains null characters
+    fn emit_text_char (&mut self, c: char) {
+        if c == '\0' {
+            self.error(ParseErrorKind::UnexpectedNullCharacter);
+            self.emit_char('\u{fffd}');
+        } else {
+            self.emit_char(c);
+        }
+    }
+
+    fn comment_eof (&mut self) {
+        self.error(ParseErrorKind::UnexpectedEof);
+        self.emit_comment();
+        self.emit_eof();
+    }
+
+    /// The `<` in RCDATA, raw text or script data
+    fn text_less_than_sign (&mut self, c: Option<char>, text_state: State, end_tag_open_state: State) {
+        if c == Some('/') {
+            self.temp.clear();
+            self.state = end_tag_open_state;
+        } else {
+            self.emit_char('<');
+            self.reconsume(text_state);
+        }
+    }
+
+    /// The `</` in RCDATA, raw text or script data
+    fn text_end_tag_open (&mut self, c: Option<char>, text_state: State, end_tag_name_state: State) {
+        match c {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.start_tag(true);
+                self.reconsume(end_tag_name_state);
+            },
+            _ => {
+                self.emit_str("</");
+                self.reconsume(text_state);
+            }
+        }
+    }
+
+    /// A possible end tag in RCDATA, raw text or script data. Only the end tag of the element
+    /// the text is in ends the text, anything else is text itself.
+    fn text_end_tag_name (&mut self, c: Option<char>, text_state: State) {
+        match c {
+            Some(c) if is_whitespace(c) && self.is_appropriate_end_tag() => self.state = State::BeforeAttributeName,
+            Some('/') if self.is_appropriate_end_tag() => self.state = State::SelfClosingStartTag,
+            Some('>') if self.is_appropriate_end_tag() => {
+                self.state = State::Data;
+                self.emit_tag();
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.tag.name.push(c.to_ascii_lowercase());
+                self.temp.push(c);
+            },
+            _ => {
+                self.emit_end_tag_open_as_text();
+                self.reconsume(text_state);
+            }
+        }
+    }
+
+    fn start_doctype_identifier (&mut self, public: bool, quote: char) {
+        self.state = match (public, quote) {
+            (true, '"') => {
+                self.doctype.public_id = Some(String::new());
+                State::DoctypePublicIdentifierDoubleQuoted
+            },
+            (true, _) => {
+                self.doctype.public_id = Some(String::new());
+                State::DoctypePublicIdentifierSingleQuoted
+            },
+            (false, '"') => {
+                self.doctype.system_id = Some(String::new());
+                State::DoctypeSystemIdentifierDoubleQuoted
+            },
+            (false, _) => {
+                self.doctype.system_id = Some(String::new());
+                State::DoctypeSystemIdentifierSingleQuoted
+            }
+        };
+    }
+
+    /// Anything unexpected in the identifiers part of a doctype
+    fn malformed_doctype (&mut self, c: Option<char>) {
+        match c {
+            Some('>') => {
+                self.error(ParseErrorKind::MalformedDoctype);
+                self.doctype.force_quirks = true;
+                self.state = State::Data;
+                self.emit_doctype();
+            },
+            Some(_) => {
+                self.error(ParseErrorKind::MalformedDoctype);
+                self.doctype.force_quirks = true;
+                self.reconsume(State::BogusDoctype);
+            },
+            None => self.doctype_eof()
+        }
+    }
+
+    fn after_doctype_name (&mut self) -> bool {
+        let public = match self.lookahead("PUBLIC", true) {
+            Some(matches) => matches,
+            None => return false
+        };
+        let system = match self.lookahead("SYSTEM", true) {
+            Some(matches) => matches,
+            None => return false
+        };
+        if public || system {
+            self.skip("PUBLIC".len());
+            self.state = if public { State::AfterDoctypePublicKeyword } else { State::AfterDoctypeSystemKeyword };
+            return true;
+        }
+
+        match self.consume() {
+            Input::Char(c) if is_whitespace(c) => {},
+            Input::Char('>') => {
+                self.state = State::Data;
+                self.emit_doctype();
+            },
+            Input::Char(_) => {
+                self.error(ParseErrorKind::MalformedDoctype);
+                self.doctype.force_quirks = true;
+                self.reconsume(State::BogusDoctype);
+            },
+            Input::Eof => self.doctype_eof(),
+            Input::Pending => return false
+        }
+        true
+    }
+
+    fn markup_declaration_open (&mut self) -> bool {
+        // The construct started at the `<!` before the current position
+        let start = Position {
+            offset: self.position.offset - 2,
+            line: self.position.line,
+            column: self.position.column - 2
+        };
+        let (comment, doctype, cdata) = match (self.lookahead("--", false), self.lookahead("DOCTYPE", true), self.lookahead("[CDATA[", false)) {
+            (Some(comment), Some(doctype), Some(cdata)) => (comment, doctype, cdata),
+            _ => return false
+        };
+
+        if comment {
+            self.skip(2);
+            self.start_comment("", start);
+            self.state = State::CommentStart;
+        } else if doctype {
+            self.skip("DOCTYPE".len());
+            self.token_start = start;
+            self.doctype = DoctypeBuilder::default();
+            self.state = State::Doctype;
+        } else if cdata && self.allow_cdata {
+            self.skip("[CDATA[".len());
+            self.state = State::CdataSection;
+        } else {
+            self.previous = self.position;
+            self.error(ParseErrorKind::BogusComment);
+            if cdata {
+                self.skip("[CDATA[".len());
+                self.start_comment("[CDATA[", start);
+            } else {
+                self.start_comment("", start);
+            }
+            self.state = State::BogusComment;
+        }
+        true
+    }
+
+    fn named_character_reference (&mut self) -> bool {
+        // Find the longest known name, which may end before the alphanumeric run does
+        let candidate: String = self.input[self.position.offset..].chars()
+            .take(entities::MAX_NAME_LENGTH)
+            .take_while(|&c| c.is_ascii_alphanumeric() || c == ';')
+            .collect();
+        let complete = candidate.len() == entities::MAX_NAME_LENGTH || candidate.contains(';')
+            || self.end_of_input || self.input.len() > self.position.offset + candidate.len();
+        if !complete {
+            return false;
+        }
+
+        let found = (1..=candidate.len()).rev()
+            .filter_map(|len| entities::lookup(&candidate[..len]).map(|value| (len, value)))
+            .next();
+        match found {
+            Some((len, value)) => {
+                let name = &candidate[..len];
+                // For historical reasons `&copy=` and `&copyx` are not references in attributes
+                let next = self.input[self.position.offset + len..].chars().next();
+                if self.in_attribute_value() && !name.ends_with(';')
+                        && next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric()) {
+                    self.temp.push_str(name);
+                } else {
+                    if !name.ends_with(';') {
+                        self.character_reference_error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                    }
+                    self.temp = value.to_string();
+                }
+                self.skip(len);
+                self.flush_character_reference();
+                self.state = self.return_state;
+            },
+            None => {
+                self.flush_character_reference();
+                self.state = State::AmbiguousAmpersand;
+            }
+        }
+        true
+    }
+
+    fn numeric_character_reference_end (&mut self) {
+        let code = self.character_reference_code;
+        let (c, invalid) = entities::numeric(code);
+        if invalid {
+            self.character_reference_error(ParseErrorKind::InvalidCharacterReference(code));
+        }
+        self.temp = c.to_string();
+        self.flush_character_reference();
+        self.state = self.return_state;
+    }
+}
@@ -0,0 +1,2571 @@
+//! Builds a dom tree out of the tokens of the `Tokenizer`, following the tree construction
+//! rules of https://html.spec.whatwg.org/multipage/parsing.html#tree-construction
+//!
+//! Missing functionality:
+//! scripting
+
+use std::borrow::Cow;
+use std::mem;
+use dom::{ AttrMap, Attribute, Doctype, Document, Namespace, NodeId, NodeType, QualName, QuirksMode, Span };
+use atom::Atom;
+use tokenizer::{ Token, ContentModel };
+use html::{ self, ParseError, ParseErrorKind };
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset
+}
+
+/// The elements that stop a search through the stack of open elements
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select
+}
+
+/// The outcome of processing a token in an insertion mode
+enum Step<'a> {
+    Done,
+    /// Process the token again, in the (possibly changed) current insertion mode
+    Reprocess(Token<'a>)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FormattingEntry {
+    Marker,
+    Element(NodeId)
+}
+
+const SPECIAL_ELEMENTS: &[&str] = &[
+    "address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote",
+    "body", "br", "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div",
+    "dl", "dt", "embed", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset",
+    "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "iframe", "img",
+    "input", "keygen", "li", "link", "listing", "main", "marquee", "menu", "meta", "nav",
+    "noembed", "noframes", "noscript", "object", "ol", "p", "param", "plaintext", "pre", "script",
+    "search", "section", "select", "source", "style", "summary", "table", "tbody", "td", "template",
+    "textarea", "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp"
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+const IMPLIED_END_TAGS: &[&str] = &["dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc"];
+
+const TABLE_SECTIONS: &[&str] = &["tbody", "tfoot", "thead"];
+
+const TABLE_CELLS: &[&str] = &["td", "th"];
+
+const IMPLIED_END_TAGS_THOROUGHLY: &[&str] = &[
+    "caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+    "tbody", "td", "tfoot", "th", "thead", "tr"
+];
+
+/// Start tags that end foreign content, as they only make sense in html
+const BREAKOUT_ELEMENTS: &[&str] = &[
+    "b", "big", "blockquote", "body", "br", "center", "code", "dd", "div", "dl", "dt", "em",
+    "embed", "h1", "h2", "h3", "h4", "h5", "h6", "head", "hr", "i", "img", "li", "listing", "menu",
+    "meta", "nobr", "ol", "p", "pre", "ruby", "s", "small", "span", "strong", "strike", "sub", "sup",
+    "table", "tt", "u", "ul", "var"
+];
+
+/// The svg element names that are not all lowercase, by their lowercase name from the tokenizer
+const SVG_ELEMENT_NAMES: &[(&str, &str)] = &[
+    ("altglyph", "altGlyph"), ("altglyphdef", "altGlyphDef"), ("altglyphitem", "altGlyphItem"),
+    ("animatecolor", "animateColor"), ("animatemotion", "animateMotion"),
+    ("animatetransform", "animateTransform"), ("clippath", "clipPath"), ("feblend", "feBlend"),
+    ("fecolormatrix", "feColorMatrix"), ("fecomponenttransfer", "feComponentTransfer"),
+    ("fecomposite", "feComposite"), ("feconvolvematrix", "feConvolveMatrix"),
+    ("fediffuselighting", "feDiffuseLighting"), ("fedisplacementmap", "feDisplacementMap"),
+    ("fedistantlight", "feDistantLight"), ("fedropshadow", "feDropShadow"), ("feflood", "feFlood"),
+    ("fefunca", "feFuncA"), ("fefuncb", "feFuncB"), ("fefuncg", "feFuncG"), ("fefuncr", "feFuncR"),
+    ("fegaussianblur", "feGaussianBlur"), ("feimage", "feImage"), ("femerge", "feMerge"),
+    ("femergenode", "feMergeNode"), ("femorphology", "feMorphology"), ("feoffset", "feOffset"),
+    ("fepointlight", "fePointLight"), ("fespecularlighting", "feSpecularLighting"),
+    ("fespotlight", "feSpotLight"), ("fetile", "feTile"), ("feturbulence", "feTurbulence"),
+    ("foreignobject", "foreignObject"), ("glyphref", "glyphRef"),
+    ("lineargradient", "linearGradient"), ("radialgradient", "radialGradient"),
+    ("textpath", "textPath")
+];
+
+/// The svg attribute names that are not all lowercase
+const SVG_ATTRIBUTE_NAMES: &[(&str, &str)] = &[
+    ("attributename", "attributeName"), ("attributetype", "attributeType"),
+    ("basefrequency", "baseFrequency"), ("baseprofile", "baseProfile"), ("calcmode", "calcMode"),
+    ("clippathunits", "clipPathUnits"), ("diffuseconstant", "diffuseConstant"),
+    ("edgemode", "edgeMode"), ("filterunits", "filterUnits"), ("glyphref", "glyphRef"),
+    ("gradienttransform", "gradientTransform"), ("gradientunits", "gradientUnits"),
+    ("kernelmatrix", "kernelMatrix"), ("kernelunitlength", "kernelUnitLength"),
+    ("keypoints", "keyPoints"), ("keysplines", "keySplines"), ("keytimes", "keyTimes"),
+    ("lengthadjust", "lengthAdjust"), ("limitingconeangle", "limitingConeAngle"),
+    ("markerheight", "markerHeight"), ("markerunits", "markerUnits"),
+    ("markerwidth", "markerWidth"), ("maskcontentunits", "maskContentUnits"),
+    ("maskunits", "maskUnits"), ("numoctaves", "numOctaves"), ("pathlength", "pathLength"),
+    ("patterncontentunits", "patternContentUnits"), ("patterntransform", "patternTransform"),
+    ("patternunits", "patternUnits"), ("pointsatx", "pointsAtX"), ("pointsaty", "pointsAtY"),
+    ("pointsatz", "pointsAtZ"), ("preservealpha", "preserveAlpha"),
+    ("preserveaspectratio", "preserveAspectRatio"), ("primitiveunits", "primitiveUnits"),
+    ("refx", "refX"), ("refy", "refY"), ("repeatcount", "repeatCount"),
+    ("repeatdur", "repeatDur"), ("requiredextensions", "requiredExtensions"),
+    ("requiredfeatures", "requiredFeatures"), ("specularconstant", "specularConstant"),
+    ("specularexponent", "specularExponent"), ("spreadmethod", "spreadMethod"),
+    ("startoffset", "startOffset"), ("stddeviation", "stdDeviation"),
+    ("stitchtiles", "stitchTiles"), ("surfacescale", "surfaceScale"),
+    ("systemlanguage", "systemLanguage"), ("tablevalues", "tableValues"), ("targetx", "targetX"),
+    ("targety", "targetY"), ("textlength", "textLength"), ("viewbox", "viewBox"),
+    ("viewtarget", "viewTarget"), ("xchannelselector", "xChannelSelector"),
+    ("ychannelselector", "yChannelSelector"), ("zoomandpan", "zoomAndPan")
+];
+
+/// The mathml attribute names that are not all lowercase
+const MATHML_ATTRIBUTE_NAMES: &[(&str, &str)] = &[("definitionurl", "definitionURL")];
+
+/// The attributes of foreign elements that are put in a namespace, with their prefix and
+/// local name
+const FOREIGN_ATTRIBUTES: &[(&str, Option<&str>, &str, Namespace)] = &[
+    ("xlink:actuate", Some("xlink"), "actuate", Namespace::XLink),
+    ("xlink:arcrole", Some("xlink"), "arcrole", Namespace::XLink),
+    ("xlink:href", Some("xlink"), "href", Namespace::XLink),
+    ("xlink:role", Some("xlink"), "role", Namespace::XLink),
+    ("xlink:show", Some("xlink"), "show", Namespace::XLink),
+    ("xlink:title", Some("xlink"), "title", Namespace::XLink),
+    ("xlink:type", Some("xlink"), "type", Namespace::XLink),
+    ("xml:lang", Some("xml"), "lang", Namespace::Xml),
+    ("xml:space", Some("xml"), "space", Namespace::Xml),
+    ("xmlns", None, "xmlns", Namespace::XmlNs),
+    ("xmlns:xlink", Some("xmlns"), "xlink", Namespace::XmlNs)
+];
+
+/// The dom attributes of an element. Foreign elements get namespaced attributes, following
+/// https://html.spec.whatwg.org/multipage/parsing.html#adjust-foreign-attributes
+fn dom_attributes (namespace: Namespace, attributes: &AttrMap) -> Vec<Attribute> {
+    attributes.iter().map(|(name, value)| {
+        let foreign = FOREIGN_ATTRIBUTES.iter().find(|&&(qualified, ..)| namespace != Namespace::Html && name == qualified);
+        let name = match foreign {
+            Some(&(_, prefix, local, namespace)) => QualName::new(prefix.map(Atom::from), namespace.atom(), Atom::from(local)),
+            None => QualName::new(None, Atom::from(""), name.clone())
+        };
+        Attribute { name, value: value.clone() }
+    }).collect()
+}
+
+/// The part of a text token after its first `start` bytes, without copying borrowed text
+fn text_after (text: Cow<'_, str>, start: usize) -> Cow<'_, str> {
+    match text {
+        Cow::Borrowed(text) => Cow::Borrowed(&text[start..]),
+        Cow::Owned(mut text) => {
+            text.drain(..start);
+            Cow::Owned(text)
+        }
+    }
+}
+
+/// Text with its null characters left out, copied only if it has any
+fn without_nulls (text: Cow<'_, str>) -> Cow<'_, str> {
+    if text.contains('\0') {
+        Cow::Owned(text.chars().filter(|&c| c != '\0').collect())
+    } else {
+        text
+    }
+}
+
+/// The value of the attribute `name` of a start tag
+fn attribute<'a> (attributes: &'a AttrMap, name: &str) -> Option<&'a str> {
+    attributes.iter().find(|&(attribute, _)| attribute == name).map(|(_, value)| &**value)
+}
+
+/// Public identifiers of doctypes that put the document in quirks mode, by their start
+const QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//", "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//", "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//", "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//", "-//ietf//dtd html 2.0 strict//", "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//", "-//ietf//dtd html 3.0//", "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//", "-//ietf//dtd html 3//", "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//", "-//ietf//dtd html level 2//", "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//", "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//", "-//ietf//dtd html strict level 3//", "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//", "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//", "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//", "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//", "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//", "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//", "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//", "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//", "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//", "-//w3c//dtd html 3.2 final//", "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//", "-//w3c//dtd html 4.0 frameset//", "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//", "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//", "-//w3o//dtd w3 html 3.0//", "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//"
+];
+
+/// Public identifiers of doctypes that put the document in quirks mode
+const QUIRKS_PUBLIC_IDS: &[&str] = &["-//w3o//dtd w3 html strict 3.0//en//", "-/w3c/dtd html 4.0 transitional/en", "html"];
+
+/// Public identifiers of html 4.01 doctypes, which ask for quirks mode without a system identifier
+/// and for limited quirks mode with one
+const HTML4_PUBLIC_ID_PREFIXES: &[&str] = &["-//w3c//dtd html 4.01 frameset//", "-//w3c//dtd html 4.01 transitional//"];
+
+/// Public identifiers of doctypes that put the document in limited quirks mode
+const LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &["-//w3c//dtd xhtml 1.0 frameset//", "-//w3c//dtd xhtml 1.0 transitional//"];
+
+/// The quirks mode a doctype asks for, following
+/// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+fn quirks_mode (name: Option<&str>, public_id: Option<&str>, system_id: Option<&str>, force_quirks: bool) -> QuirksMode {
+    let public_id = public_id.map(|id| id.to_ascii_lowercase());
+    let public_id = public_id.as_deref();
+    let system_id = system_id.map(|id| id.to_ascii_lowercase());
+    let starts_with_any = |prefixes: &[&str]| public_id.is_some_and(|id| prefixes.iter().any(|prefix| id.starts_with(prefix)));
+
+    if force_quirks || name != Some("html")
+        || public_id.is_some_and(|id| QUIRKS_PUBLIC_IDS.contains(&id))
+        || system_id.as_ref().is_some_and(|id| id == "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || starts_with_any(QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_none() && starts_with_any(HTML4_PUBLIC_ID_PREFIXES)) {
+        QuirksMode::Quirks
+    } else if starts_with_any(LIMITED_QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_some() && starts_with_any(HTML4_PUBLIC_ID_PREFIXES)) {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+/// Returns true if the text only consists of the whitespace characters html cares about
+fn is_whitespace (text: &str) -> bool {
+    text.chars().all(is_html_whitespace)
+}
+
+fn is_html_whitespace (c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0c' | '\r' | ' ')
+}
+
+/// Split text into its leading whitespace and the rest
+fn split_whitespace (text: &str) -> (&str, &str) {
+    let end = text.find(|c| !is_html_whitespace(c)).unwrap_or(text.len());
+    text.split_at(end)
+}
+
+/// Builds the tree straight into a `Document`, so the nodes keep their ids while the rest
+/// of the input is parsed. Elements implied by the tree construction rules have no span.
+#[derive(Debug)]
+pub struct TreeBuilder {
+    document: Document,
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    open_elements: Vec<NodeId>,
+    active_formatting: Vec<FormattingEntry>,
+    /// The insertion modes of the open `template` elements
+    template_modes: Vec<InsertionMode>,
+    head: Option<NodeId>,
+    form: Option<NodeId>,
+    frameset_ok: bool,
+    foster_parenting: bool,
+    skip_newline: bool,
+    content_model: Option<ContentModel>,
+    /// Text in a table, with the span of each token, until it is known whether it is only
+    /// whitespace
+    pending_table_text: Vec<(String, Span)>,
+    /// The element a fragment is parsed for, which is not part of the tree
+    context: Option<NodeId>,
+    /// The source of the token being processed
+    span: Span,
+    /// The name of the token being processed, if it is a start tag
+    start_tag: Option<Atom>,
+    /// Whether the self-closing flag of the start tag being processed was honored
+    self_closing_acknowledged: bool,
+    /// Tree construction errors, at the position of the token that caused them
+    pub errors: Vec<ParseError>
+}
+
+impl Default for TreeBuilder {
+    fn default () -> TreeBuilder {
+        TreeBuilder::new()
+    }
+}
+
+impl TreeBuilder {
+    pub fn new () -> TreeBuilder {
+        TreeBuilder {
+            document: Document::new(),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            template_modes: Vec::new(),
+            head: None,
+            form: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            skip_newline: false,
+            content_model: None,
+            pending_table_text: Vec::new(),
+            context: None,
+            span: Span::default(),
+            start_tag: None,
+            self_closing_acknowledged: false,
+            errors: Vec::new()
+        }
+    }
+
+    /// Create a tree builder for the content of a `context` element, following
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-html-fragments
+    pub fn for_fragment (context: &str) -> TreeBuilder {
+        let mut builder = TreeBuilder::new();
+        let namespace = match context {
+            "svg" => Namespace::Svg,
+            "math" => Namespace::MathMl,
+            _ => Namespace::Html
+        };
+        if context == "template" {
+            builder.template_modes.push(InsertionMode::InTemplate);
+        }
+        let context = builder.create_element_ns(namespace, context, &AttrMap::new());
+        builder.context = Some(context);
+
+        let html = builder.create_element("html", &AttrMap::new());
+        let root = builder.document.root();
+        builder.append(root, html);
+        builder.open_elements.push(html);
+        builder.reset_insertion_mode();
+        builder
+    }
+
+    /// Add a token, made from the `span` of the input, to the tree. Returns how the
+    /// tokenizer has to scan the following input, if that changes after this token.
+    pub fn process_token (&mut self, token: Token, span: Span) -> Option<ContentModel> {
+        self.span = span;
+        self.start_tag = match token {
+            Token::StartTag { ref name, .. } => Some(name.clone()),
+            _ => None
+        };
+        // The element this end tag closes, if it does, ends with it
+        let end_tag = match token {
+            Token::EndTag { ref name } => self.open_elements.iter()
+                .rposition(|&element| self.local_name(element).eq_ignore_ascii_case(name))
+                .map(|position| (position, self.open_elements[position])),
+            _ => None
+        };
+
+        // A newline right after `<pre>`, `<listing>` or `<textarea>` is not part of the content
+        let token = match token {
+            Token::Text(text) if self.skip_newline && text.starts_with('\n') => Token::Text(text_after(text, 1)),
+            token => token
+        };
+        self.skip_newline = false;
+        if let Token::Text(ref text) = token {
+            if text.is_empty() {
+                return None;
+            }
+        }
+
+        let self_closing = match token {
+            Token::StartTag { ref name, self_closing: true, .. } => Some(name.clone()),
+            _ => None
+        };
+        self.self_closing_acknowledged = false;
+
+        let mut token = token;
+        loop {
+            let mode = self.mode;
+            let step = if self.is_foreign_token(&token) { self.foreign_content(token) } else { self.step(mode, token) };
+            match step {
+                Step::Done => break,
+                Step::Reprocess(next) => token = next
+            }
+        }
+
+        if let Some(name) = self_closing {
+            if !self.self_closing_acknowledged && !html::is_void_element(&name) {
+                self.error(ParseErrorKind::SelfClosingNonVoidElement(name.to_string()));
+            }
+        }
+        if let Some((position, element)) = end_tag {
+            if self.open_elements.get(position) != Some(&element) {
+                let element_span = self.document[element].span.map(|element_span| Span { end: span.end, ..element_span });
+                self.document.set_span(element, element_span);
+            }
+        }
+        self.content_model.take()
+    }
+
+    /// Returns true while the tokenizer is in svg or mathml, where `<![CDATA[` starts a
+    /// CDATA section instead of a bogus comment
+    pub fn in_foreign_content (&self) -> bool {
+        self.adjusted_current().is_some_and(|node| self.namespace(node) != Some(Namespace::Html))
+    }
+
+    /// Return the finished document
+    pub fn finish (mut self) -> Document {
+        end_spans(&mut self.document);
+        self.document
+    }
+
+    /// Copy the nodes of a fragment into `document`, see `for_fragment`. They are not added to
+    /// its tree.
+    pub fn finish_fragment (self, document: &mut Document) -> Vec<NodeId> {
+        let fragment = self.finish();
+        let html = fragment.document_element().expect("a fragment is parsed into an html element");
+        fragment.children(html).map(|child| document.import_node(&fragment, child)).collect()
+    }
+
+    /// The document built so far. Elements that are still open end with their opening tag.
+    pub fn document (&self) -> &Document {
+        &self.document
+    }
+
+    fn error (&mut self, kind: ParseErrorKind) {
+        self.errors.push(ParseError {
+            kind,
+            offset: self.span.start,
+            line: self.span.line,
+            column: self.span.column
+        });
+    }
+
+    fn step<'a> (&mut self, mode: InsertionMode, token: Token<'a>) -> Step<'a> {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token)
+        }
+    }
+
+    /// Handle leading whitespace of a text token with `handle`, and return the rest of the
+    /// text for reprocessing, if there is any
+    fn split_text<'a, F> (&mut self, text: Cow<'a, str>, handle: F) -> Step<'a> where F: FnOnce(&mut TreeBuilder, &str) {
+        let (whitespace, rest) = split_whitespace(&text);
+        if !whitespace.is_empty() {
+            handle(self, whitespace);
+        }
+        if rest.is_empty() {
+            Step::Done
+        } else {
+            let length = whitespace.len();
+            Step::Reprocess(Token::Text(text_after(text, length)))
+        }
+    }
+
+    // Insertion modes
+
+    fn initial<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                match self.split_text(text, |_, _| {}) {
+                    Step::Done => Step::Done,
+                    Step::Reprocess(token) => self.initial_anything_else(token)
+                }
+            },
+            Token::Comment(data) => {
+                let root = self.document.root();
+                self.insert_comment_in(data, root);
+                Step::Done
+            },
+            Token::Doctype { name, public_id, system_id, force_quirks } => {
+                self.document.set_quirks_mode(quirks_mode(name.as_deref(), public_id.as_deref(), system_id.as_deref(), force_quirks));
+                let doctype = self.document.doctype_node(Doctype { name: name.unwrap_or_default(), public_id, system_id });
+                self.document.set_span(doctype, Some(self.span));
+                let root = self.document.root();
+                self.append(root, doctype);
+                self.mode = InsertionMode::BeforeHtml;
+                Step::Done
+            },
+            token => self.initial_anything_else(token)
+        }
+    }
+
+    fn initial_anything_else<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        self.error(ParseErrorKind::MissingDoctype);
+        self.document.set_quirks_mode(QuirksMode::Quirks);
+        self.mode = InsertionMode::BeforeHtml;
+        Step::Reprocess(token)
+    }
+
+    fn before_html<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::Comment(data) => {
+                let root = self.document.root();
+                self.insert_comment_in(data, root);
+                Step::Done
+            },
+            Token::Text(text) => {
+                match self.split_text(text, |_, _| {}) {
+                    Step::Done => Step::Done,
+                    Step::Reprocess(token) => self.before_html_anything_else(token)
+                }
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "html" => {
+                let html = self.create_element(name, attributes);
+                let root = self.document.root();
+                self.append(root, html);
+                self.open_elements.push(html);
+                self.mode = InsertionMode::BeforeHead;
+                Step::Done
+            },
+            Token::EndTag { ref name } if !["head", "body", "html", "br"].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            token => self.before_html_anything_else(token)
+        }
+    }
+
+    fn before_html_anything_else<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        let html = self.create_element("html", &AttrMap::new());
+        let root = self.document.root();
+        self.append(root, html);
+        self.open_elements.push(html);
+        self.mode = InsertionMode::BeforeHead;
+        Step::Reprocess(token)
+    }
+
+    fn before_head<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                match self.split_text(text, |_, _| {}) {
+                    Step::Done => Step::Done,
+                    Step::Reprocess(token) => self.before_head_anything_else(token)
+                }
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, ref attributes, .. } if name == "head" => {
+                let head = self.insert_element(name, attributes);
+                self.head = Some(head);
+                self.mode = InsertionMode::InHead;
+                Step::Done
+            },
+            Token::EndTag { ref name } if !["head", "body", "html", "br"].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            token => self.before_head_anything_else(token)
+        }
+    }
+
+    fn before_head_anything_else<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        let head = self.insert_element("head", &AttrMap::new());
+        self.head = Some(head);
+        self.mode = InsertionMode::InHead;
+        Step::Reprocess(token)
+    }
+
+    fn in_head<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                match self.split_text(text, |builder, whitespace| builder.insert_text(whitespace)) {
+                    Step::Done => Step::Done,
+                    Step::Reprocess(token) => self.in_head_anything_else(token)
+                }
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, ref attributes, .. }
+                    if ["base", "basefont", "bgsound", "link", "meta"].contains(&&**name) => {
+                self.insert_element(name, attributes);
+                self.open_elements.pop();
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. }
+                    if ["title", "noframes", "style", "script"].contains(&&**name) => {
+                self.insert_text_element(name, attributes);
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "template" => {
+                self.insert_element(name, attributes);
+                self.active_formatting.push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTemplate;
+                self.template_modes.push(InsertionMode::InTemplate);
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "template" => {
+                if !self.template_on_stack() {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.close_template();
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "head" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "head" => {
+                self.open_elements.pop();
+                self.mode = InsertionMode::AfterHead;
+                Step::Done
+            },
+            Token::EndTag { ref name } if !["body", "html", "br"].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            token => self.in_head_anything_else(token)
+        }
+    }
+
+    fn in_head_anything_else<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        self.open_elements.pop();
+        self.mode = InsertionMode::AfterHead;
+        Step::Reprocess(token)
+    }
+
+    fn after_head<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                match self.split_text(text, |builder, whitespace| builder.insert_text(whitespace)) {
+                    Step::Done => Step::Done,
+                    Step::Reprocess(token) => self.after_head_anything_else(token)
+                }
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, ref attributes, .. } if name == "body" => {
+                self.insert_element(name, attributes);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InBody;
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "frameset" => {
+                self.insert_element(name, attributes);
+                self.mode = InsertionMode::InFrameset;
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if [
+                "base", "basefont", "bgsound", "link", "meta", "noframes", "script", "style", "template", "title"
+            ].contains(&&**name) => {
+                // Misplaced head content still goes into the head
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                let head = self.head.expect("the head element is created before the after head mode");
+                self.open_elements.push(head);
+                let step = self.in_head(token);
+                self.remove_from_stack(head);
+                step
+            },
+            Token::EndTag { ref name } if name == "template" => self.in_head(token),
+            Token::StartTag { ref name, .. } if name == "head" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                Step::Done
+            },
+            Token::EndTag { ref name } if !["body", "html", "br"].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            token => self.after_head_anything_else(token)
+        }
+    }
+
+    fn after_head_anything_else<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        self.insert_element("body", &AttrMap::new());
+        self.mode = InsertionMode::InBody;
+        Step::Reprocess(token)
+    }
+
+    fn in_body<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                let text = without_nulls(text);
+                if text.is_empty() {
+                    return Step::Done;
+                }
+                self.reconstruct_active_formatting();
+                self.insert_text(&text);
+                if !is_whitespace(&text) {
+                    self.frameset_ok = false;
+                }
+                Step::Done
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { name, attributes, self_closing } => self.in_body_start_tag(name, attributes, self_closing),
+            Token::EndTag { name } => self.in_body_end_tag(name),
+            Token::Eof if !self.template_modes.is_empty() => self.in_template(token),
+            Token::Eof => {
+                self.check_unclosed_elements();
+                Step::Done
+            }
+        }
+    }
+
+    fn in_body_start_tag<'a> (&mut self, name: Atom, attributes: AttrMap, self_closing: bool) -> Step<'a> {
+        match &*name {
+            "html" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                if !self.template_on_stack() {
+                    let html = self.open_elements[0];
+                    self.merge_attributes(html, attributes);
+                }
+            },
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template" | "title" => {
+                return self.in_head(Token::StartTag { name, attributes, self_closing });
+            },
+            "body" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                if self.open_elements.len() > 1 && self.name(self.open_elements[1]) == "body" && !self.template_on_stack() {
+                    self.frameset_ok = false;
+                    let body = self.open_elements[1];
+                    self.merge_attributes(body, attributes);
+                }
+            },
+            "frameset" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                if self.frameset_ok && self.open_elements.len() > 1 && self.name(self.open_elements[1]) == "body" {
+                    let body = self.open_elements[1];
+                    if let Some(parent) = self.document[body].parent() {
+                        self.document.remove_child(parent, body);
+                    }
+                    self.open_elements.truncate(1);
+                    self.insert_element(&name, &attributes);
+                    self.mode = InsertionMode::InFrameset;
+                }
+            },
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog" | "dir"
+                    | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+                    | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section"
+                    | "summary" | "ul" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&name, &attributes);
+            },
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_in_button_scope();
+                if HEADINGS.contains(&self.current_name()) {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                    self.open_elements.pop();
+                }
+                self.insert_element(&name, &attributes);
+            },
+            "pre" | "listing" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&name, &attributes);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            },
+            "form" => {
+                // Forms in templates are not associated with the form pointer
+                let in_template = self.template_on_stack();
+                if self.form.is_some() && !in_template {
+                    self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                } else {
+                    self.close_p_in_button_scope();
+                    let form = self.insert_element(&name, &attributes);
+                    if !in_template {
+                        self.form = Some(form);
+                    }
+                }
+            },
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                // Close an open list item of the same kind
+                let closes: &[&str] = if name == "li" { &["li"] } else { &["dd", "dt"] };
+                for &node in self.open_elements.clone().iter().rev() {
+                    let node_name = self.name(node).to_string();
+                    if closes.contains(&&*node_name) {
+                        self.generate_implied_end_tags(Some(&node_name));
+                        if self.current_name() != node_name {
+                            self.error(ParseErrorKind::MisnestedTag(node_name.clone()));
+                        }
+                        self.pop_until(&[&node_name]);
+                        break;
+                    }
+                    if self.is_special(node) && !["address", "div", "p"].contains(&&*node_name) {
+                        break;
+                    }
+                }
+                self.close_p_in_button_scope();
+                self.insert_element(&name, &attributes);
+            },
+            "plaintext" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&name, &attributes);
+                self.content_model = Some(ContentModel::PlainText);
+            },
+            "button" => {
+                if self.in_scope(&["button"], Scope::Default) {
+                    self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_active_formatting();
+                self.insert_element(&name, &attributes);
+                self.frameset_ok = false;
+            },
+            "a" => {
+                if let Some(a) = self.active_formatting_element("a") {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                    self.adoption_agency("a");
+                    self.remove_from_active_formatting(a);
+                    self.remove_from_stack(a);
+                }
+                self.reconstruct_active_formatting();
+                let a = self.insert_element(&name, &attributes);
+                self.push_active_formatting(a);
+            },
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong" | "tt" | "u" => {
+                self.reconstruct_active_formatting();
+                let element = self.insert_element(&name, &attributes);
+                self.push_active_formatting(element);
+            },
+            "nobr" => {
+                self.reconstruct_active_formatting();
+                if self.in_scope(&["nobr"], Scope::Default) {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                    self.adoption_agency("nobr");
+                    self.reconstruct_active_formatting();
+                }
+                let element = self.insert_element(&name, &attributes);
+                self.push_active_formatting(element);
+            },
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_active_formatting();
+                self.insert_element(&name, &attributes);
+                self.active_formatting.push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+            },
+            "table" => {
+                // Old pages expect tables in paragraphs
+                if self.document.quirks_mode() != QuirksMode::Quirks {
+                    self.close_p_in_button_scope();
+                }
+                self.insert_element(&name, &attributes);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            },
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_active_formatting();
+                self.insert_element(&name, &attributes);
+                self.open_elements.pop();
+                self.frameset_ok = false;
+            },
+            "input" => {
+                self.reconstruct_active_formatting();
+                self.insert_element(&name, &attributes);
+                self.open_elements.pop();
+                if !attribute(&attributes, "type").is_some_and(|t| t.eq_ignore_ascii_case("hidden")) {
+                    self.frameset_ok = false;
+                }
+            },
+            "param" | "source" | "track" => {
+                self.insert_element(&name, &attributes);
+                self.open_elements.pop();
+            },
+            "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&name, &attributes);
+                self.open_elements.pop();
+                self.frameset_ok = false;
+            },
+            "image" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                return Step::Reprocess(Token::StartTag { name: Atom::from("img"), attributes, self_closing });
+            },
+            "textarea" => {
+                self.insert_text_element(&name, &attributes);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            },
+            "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_active_formatting();
+                self.frameset_ok = false;
+                self.insert_text_element(&name, &attributes);
+            },
+            "iframe" => {
+                self.frameset_ok = false;
+                self.insert_text_element(&name, &attributes);
+            },
+            "noembed" => {
+                self.insert_text_element(&name, &attributes);
+            },
+            "select" => {
+                self.reconstruct_active_formatting();
+                self.insert_element(&name, &attributes);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable | InsertionMode::InCaption | InsertionMode::InTableBody
+                        | InsertionMode::InRow | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect
+                };
+            },
+            "optgroup" | "option" => {
+                if self.current_name() == "option" {
+                    self.open_elements.pop();
+                }
+                self.reconstruct_active_formatting();
+                self.insert_element(&name, &attributes);
+            },
+            "rb" | "rtc" | "rp" | "rt" => {
+                if self.in_scope(&["ruby"], Scope::Default) {
+                    let except = if name == "rp" || name == "rt" { Some("rtc") } else { None };
+                    self.generate_implied_end_tags(except);
+                    let expected = if except.is_some() { ["ruby", "rtc"].contains(&self.current_name()) }
+                                   else { self.current_name() == "ruby" };
+                    if !expected {
+                        self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                    }
+                }
+                self.insert_element(&name, &attributes);
+            },
+            "math" | "svg" => {
+                self.reconstruct_active_formatting();
+                let namespace = if name == "svg" { Namespace::Svg } else { Namespace::MathMl };
+                self.insert_foreign_element(namespace, &name, attributes, self_closing);
+            },
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+            },
+            _ => {
+                self.reconstruct_active_formatting();
+                self.insert_element(&name, &attributes);
+            }
+        }
+        Step::Done
+    }
+
+    fn in_body_end_tag<'a> (&mut self, name: Atom) -> Step<'a> {
+        match &*name {
+            "body" | "html" => {
+                if !self.in_scope(&["body"], Scope::Default) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.check_unclosed_elements();
+                self.mode = InsertionMode::AfterBody;
+                if name == "html" {
+                    return Step::Reprocess(Token::EndTag { name });
+                }
+            },
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+                    | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure"
+                    | "footer" | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol"
+                    | "pre" | "search" | "section" | "summary" | "ul" => {
+                if !self.in_scope(&[&name], Scope::Default) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.generate_implied_end_tags(None);
+                if name != self.current_name() {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                }
+                self.pop_until(&[&name]);
+            },
+            "template" => return self.in_head(Token::EndTag { name }),
+            "form" if self.template_on_stack() => {
+                if !self.in_scope(&["form"], Scope::Default) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.generate_implied_end_tags(None);
+                if self.current_name() != "form" {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                }
+                self.pop_until(&["form"]);
+            },
+            "form" => {
+                let form = self.form.take();
+                match form {
+                    Some(form) if self.node_in_scope(form, Scope::Default) => {
+                        self.generate_implied_end_tags(None);
+                        if self.current() != Some(form) {
+                            self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                        }
+                        self.remove_from_stack(form);
+                    },
+                    _ => self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()))
+                }
+            },
+            "p" => {
+                if !self.in_scope(&["p"], Scope::Button) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    self.insert_element("p", &AttrMap::new());
+                }
+                self.close_p();
+            },
+            "li" | "dd" | "dt" => {
+                let scope = if name == "li" { Scope::ListItem } else { Scope::Default };
+                if !self.in_scope(&[&name], scope) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.generate_implied_end_tags(Some(&name));
+                if name != self.current_name() {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                }
+                self.pop_until(&[&name]);
+            },
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if !self.in_scope(HEADINGS, Scope::Default) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.generate_implied_end_tags(None);
+                if name != self.current_name() {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                }
+                self.pop_until(HEADINGS);
+            },
+            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small" | "strike"
+                    | "strong" | "tt" | "u" => {
+                if !self.adoption_agency(&name) {
+                    self.any_other_end_tag(&name);
+                }
+            },
+            "applet" | "marquee" | "object" => {
+                if !self.in_scope(&[&name], Scope::Default) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.generate_implied_end_tags(None);
+                if name != self.current_name() {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                }
+                self.pop_until(&[&name]);
+                self.clear_active_formatting_to_marker();
+            },
+            "br" => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                return self.in_body_start_tag(name, AttrMap::new(), false);
+            },
+            _ => self.any_other_end_tag(&name)
+        }
+        Step::Done
+    }
+
+    fn any_other_end_tag (&mut self, name: &str) {
+        for &node in self.open_elements.clone().iter().rev() {
+            if self.name(node) == name {
+                self.generate_implied_end_tags(Some(name));
+                if self.current() != Some(node) {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                }
+                while self.open_elements.pop() != Some(node) {}
+                return;
+            }
+            if self.is_special(node) {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                return;
+            }
+        }
+    }
+
+    fn text<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                self.insert_text(&text);
+                Step::Done
+            },
+            Token::Eof => {
+                let name = self.current_name().to_string();
+                self.error(ParseErrorKind::UnclosedElement(name.to_string()));
+                self.open_elements.pop();
+                self.mode = self.original_mode;
+                Step::Reprocess(Token::Eof)
+            },
+            Token::EndTag { ref name } if name == self.current_name() => {
+                self.open_elements.pop();
+                self.mode = self.original_mode;
+                Step::Done
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::StartTag { name, .. } => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                Step::Done
+            },
+            Token::EndTag { name } => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            }
+        }
+    }
+
+    fn in_table<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(_) if ["table", "tbody", "tfoot", "thead", "tr"].contains(&self.current_name()) => {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.mode = InsertionMode::InTableText;
+                Step::Reprocess(token)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "caption" => {
+                self.clear_stack_to(&["table"]);
+                self.active_formatting.push(FormattingEntry::Marker);
+                self.insert_element(name, attributes);
+                self.mode = InsertionMode::InCaption;
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "colgroup" => {
+                self.clear_stack_to(&["table"]);
+                self.insert_element(name, attributes);
+                self.mode = InsertionMode::InColumnGroup;
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "col" => {
+                self.clear_stack_to(&["table"]);
+                self.insert_element("colgroup", &AttrMap::new());
+                self.mode = InsertionMode::InColumnGroup;
+                Step::Reprocess(token)
+            },
+            Token::StartTag { ref name, ref attributes, .. } if TABLE_SECTIONS.contains(&&**name) => {
+                self.clear_stack_to(&["table"]);
+                self.insert_element(name, attributes);
+                self.mode = InsertionMode::InTableBody;
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "tr" || TABLE_CELLS.contains(&&**name) => {
+                self.clear_stack_to(&["table"]);
+                self.insert_element("tbody", &AttrMap::new());
+                self.mode = InsertionMode::InTableBody;
+                Step::Reprocess(token)
+            },
+            Token::StartTag { ref name, .. } if name == "table" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                if !self.in_scope(&["table"], Scope::Table) {
+                    return Step::Done;
+                }
+                self.pop_until(&["table"]);
+                self.reset_insertion_mode();
+                Step::Reprocess(token)
+            },
+            Token::EndTag { ref name } if name == "table" => {
+                if !self.in_scope(&["table"], Scope::Table) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.pop_until(&["table"]);
+                self.reset_insertion_mode();
+                Step::Done
+            },
+            Token::EndTag { ref name } if [
+                "body", "caption", "col", "colgroup", "html", "tbody", "td", "tfoot", "th", "thead", "tr"
+            ].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if ["style", "script", "template"].contains(&&**name) => self.in_head(token),
+            Token::EndTag { ref name } if name == "template" => self.in_head(token),
+            Token::StartTag { ref name, ref attributes, .. } if name == "input"
+                    && attribute(attributes, "type").is_some_and(|t| t.eq_ignore_ascii_case("hidden")) => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                self.insert_element(name, attributes);
+                self.open_elements.pop();
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "form" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                if self.form.is_none() && !self.template_on_stack() {
+                    let form = self.insert_element(name, attributes);
+                    self.form = Some(form);
+                    self.open_elements.pop();
+                }
+                Step::Done
+            },
+            Token::Eof => self.in_body(token),
+            token => self.in_table_anything_else(token)
+        }
+    }
+
+    /// Process a token that does not belong in a table with the in body rules, moving any
+    /// content it creates in front of the table
+    fn in_table_anything_else<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(_) => self.error(ParseErrorKind::UnexpectedText),
+            Token::StartTag { ref name, .. } => self.error(ParseErrorKind::UnexpectedStartTag(name.to_string())),
+            Token::EndTag { ref name } => self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string())),
+            _ => {}
+        }
+        self.foster_parenting = true;
+        let step = self.in_body(token);
+        self.foster_parenting = false;
+        step
+    }
+
+    fn in_table_text<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                let text = without_nulls(text);
+                let span = self.span;
+                self.pending_table_text.push((text.into_owned(), span));
+                Step::Done
+            },
+            token => {
+                let pending = mem::take(&mut self.pending_table_text);
+                let span = self.span;
+                let whitespace = pending.iter().all(|(text, _)| is_whitespace(text));
+                for (text, text_span) in pending {
+                    // The text is inserted with its own span, not the one of the token after it
+                    self.span = text_span;
+                    if whitespace {
+                        self.insert_text(&text);
+                    } else {
+                        self.in_table_anything_else(Token::Text(Cow::Owned(text)));
+                    }
+                }
+                self.span = span;
+                self.mode = self.original_mode;
+                Step::Reprocess(token)
+            }
+        }
+    }
+
+    fn in_caption<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::EndTag { ref name } if name == "caption" => {
+                self.close_caption();
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if [
+                "caption", "col", "colgroup", "tbody", "td", "tfoot", "th", "thead", "tr"
+            ].contains(&&**name) => {
+                if self.close_caption() {
+                    Step::Reprocess(token)
+                } else {
+                    Step::Done
+                }
+            },
+            Token::EndTag { ref name } if name == "table" => {
+                if self.close_caption() {
+                    Step::Reprocess(token)
+                } else {
+                    Step::Done
+                }
+            },
+            Token::EndTag { ref name } if [
+                "body", "col", "colgroup", "html", "tbody", "td", "tfoot", "th", "thead", "tr"
+            ].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            token => self.in_body(token)
+        }
+    }
+
+    /// Close the open caption element, returns false if there is none
+    fn close_caption (&mut self) -> bool {
+        if !self.in_scope(&["caption"], Scope::Table) {
+            self.error(ParseErrorKind::UnexpectedClosingTag("caption".to_string()));
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        if self.current_name() != "caption" {
+            self.error(ParseErrorKind::MisnestedTag("caption".to_string()));
+        }
+        self.pop_until(&["caption"]);
+        self.clear_active_formatting_to_marker();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    fn in_column_group<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                match self.split_text(text, |builder, whitespace| builder.insert_text(whitespace)) {
+                    Step::Done => Step::Done,
+                    Step::Reprocess(token) => self.in_column_group_anything_else(token)
+                }
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, ref attributes, .. } if name == "col" => {
+                self.insert_element(name, attributes);
+                self.open_elements.pop();
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "colgroup" => {
+                if self.current_name() != "colgroup" {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                } else {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                }
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "col" => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } | Token::EndTag { ref name } if name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            token => self.in_column_group_anything_else(token)
+        }
+    }
+
+    fn in_column_group_anything_else<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        if self.current_name() != "colgroup" {
+            self.error(ParseErrorKind::UnexpectedText);
+            return Step::Done;
+        }
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTable;
+        Step::Reprocess(token)
+    }
+
+    fn in_table_body<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::StartTag { ref name, ref attributes, .. } if name == "tr" => {
+                self.clear_stack_to(TABLE_SECTIONS);
+                self.insert_element(name, attributes);
+                self.mode = InsertionMode::InRow;
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if TABLE_CELLS.contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                self.clear_stack_to(TABLE_SECTIONS);
+                self.insert_element("tr", &AttrMap::new());
+                self.mode = InsertionMode::InRow;
+                Step::Reprocess(token)
+            },
+            Token::EndTag { ref name } if TABLE_SECTIONS.contains(&&**name) => {
+                if !self.in_scope(&[name], Scope::Table) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.clear_stack_to(TABLE_SECTIONS);
+                self.open_elements.pop();
+                self.mode = InsertionMode::InTable;
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if ["caption", "col", "colgroup", "tbody", "tfoot", "thead"].contains(&&**name) => {
+                self.close_table_section(token)
+            },
+            Token::EndTag { ref name } if name == "table" => self.close_table_section(token),
+            Token::EndTag { ref name } if [
+                "body", "caption", "col", "colgroup", "html", "td", "th", "tr"
+            ].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            token => self.in_table(token)
+        }
+    }
+
+    /// Close the open tbody, thead or tfoot and reprocess the token in the table
+    fn close_table_section<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        if !self.in_scope(TABLE_SECTIONS, Scope::Table) {
+            match token {
+                Token::StartTag { name, .. } => self.error(ParseErrorKind::UnexpectedStartTag(name.to_string())),
+                Token::EndTag { name } => self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string())),
+                _ => {}
+            }
+            return Step::Done;
+        }
+        self.clear_stack_to(TABLE_SECTIONS);
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTable;
+        Step::Reprocess(token)
+    }
+
+    fn in_row<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::StartTag { ref name, ref attributes, .. } if TABLE_CELLS.contains(&&**name) => {
+                self.clear_stack_to(&["tr"]);
+                self.insert_element(name, attributes);
+                self.mode = InsertionMode::InCell;
+                self.active_formatting.push(FormattingEntry::Marker);
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "tr" => {
+                self.close_row();
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if [
+                "caption", "col", "colgroup", "tbody", "tfoot", "thead", "tr"
+            ].contains(&&**name) => {
+                if self.close_row() {
+                    Step::Reprocess(token)
+                } else {
+                    Step::Done
+                }
+            },
+            Token::EndTag { ref name } if name == "table" => {
+                if self.close_row() {
+                    Step::Reprocess(token)
+                } else {
+                    Step::Done
+                }
+            },
+            Token::EndTag { ref name } if TABLE_SECTIONS.contains(&&**name) => {
+                if !self.in_scope(&[name], Scope::Table) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                if self.close_row() {
+                    Step::Reprocess(token)
+                } else {
+                    Step::Done
+                }
+            },
+            Token::EndTag { ref name } if [
+                "body", "caption", "col", "colgroup", "html", "td", "th"
+            ].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            token => self.in_table(token)
+        }
+    }
+
+    /// Close the open table row, returns false if there is none
+    fn close_row (&mut self) -> bool {
+        if !self.in_scope(&["tr"], Scope::Table) {
+            self.error(ParseErrorKind::UnexpectedClosingTag("tr".to_string()));
+            return false;
+        }
+        self.clear_stack_to(&["tr"]);
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    fn in_cell<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::EndTag { ref name } if TABLE_CELLS.contains(&&**name) => {
+                if !self.in_scope(&[name], Scope::Table) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.generate_implied_end_tags(None);
+                if name != self.current_name() {
+                    self.error(ParseErrorKind::MisnestedTag(name.to_string()));
+                }
+                self.pop_until(&[name]);
+                self.clear_active_formatting_to_marker();
+                self.mode = InsertionMode::InRow;
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if [
+                "caption", "col", "colgroup", "tbody", "td", "tfoot", "th", "thead", "tr"
+            ].contains(&&**name) => {
+                if !self.in_scope(TABLE_CELLS, Scope::Table) {
+                    self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.close_cell();
+                Step::Reprocess(token)
+            },
+            Token::EndTag { ref name } if ["body", "caption", "col", "colgroup", "html"].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            Token::EndTag { ref name } if ["table", "tbody", "tfoot", "thead", "tr"].contains(&&**name) => {
+                if !self.in_scope(&[name], Scope::Table) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.close_cell();
+                Step::Reprocess(token)
+            },
+            token => self.in_body(token)
+        }
+    }
+
+    fn close_cell (&mut self) {
+        self.generate_implied_end_tags(None);
+        if !TABLE_CELLS.contains(&self.current_name()) {
+            let name = self.current_name().to_string();
+            self.error(ParseErrorKind::UnclosedElement(name.to_string()));
+        }
+        self.pop_until(TABLE_CELLS);
+        self.clear_active_formatting_to_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    fn in_select<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                let text = without_nulls(text);
+                if !text.is_empty() {
+                    self.insert_text(&text);
+                }
+                Step::Done
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, ref attributes, .. } if name == "option" => {
+                if self.current_name() == "option" {
+                    self.open_elements.pop();
+                }
+                self.insert_element(name, attributes);
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "optgroup" || name == "hr" => {
+                if self.current_name() == "option" {
+                    self.open_elements.pop();
+                }
+                if self.current_name() == "optgroup" {
+                    self.open_elements.pop();
+                }
+                self.insert_element(name, attributes);
+                if name == "hr" {
+                    self.open_elements.pop();
+                }
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "optgroup" => {
+                let len = self.open_elements.len();
+                if self.current_name() == "option" && len > 1 && self.name(self.open_elements[len - 2]) == "optgroup" {
+                    self.open_elements.pop();
+                }
+                if self.current_name() == "optgroup" {
+                    self.open_elements.pop();
+                } else {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                }
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "option" => {
+                if self.current_name() == "option" {
+                    self.open_elements.pop();
+                } else {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                }
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "select" => {
+                if !self.in_scope(&["select"], Scope::Select) {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "select" => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                if self.in_scope(&["select"], Scope::Select) {
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                }
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if ["input", "keygen", "textarea"].contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                if !self.in_scope(&["select"], Scope::Select) {
+                    return Step::Done;
+                }
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                Step::Reprocess(token)
+            },
+            Token::StartTag { ref name, .. } if name == "script" || name == "template" => self.in_head(token),
+            Token::EndTag { ref name } if name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            Token::StartTag { name, .. } => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                Step::Done
+            },
+            Token::EndTag { name } => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            }
+        }
+    }
+
+    fn in_select_in_table<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        const TABLE_TAGS: &[&str] = &["caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th"];
+        match token {
+            Token::StartTag { ref name, .. } if TABLE_TAGS.contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedStartTag(name.to_string()));
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                Step::Reprocess(token)
+            },
+            Token::EndTag { ref name } if TABLE_TAGS.contains(&&**name) => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                if !self.in_scope(&[name], Scope::Table) {
+                    return Step::Done;
+                }
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                Step::Reprocess(token)
+            },
+            token => self.in_select(token)
+        }
+    }
+
+    fn in_template<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(_) | Token::Comment(_) | Token::Doctype { .. } => self.in_body(token),
+            Token::StartTag { ref name, .. } if [
+                "base", "basefont", "bgsound", "link", "meta", "noframes", "script", "style", "template", "title"
+            ].contains(&&**name) => self.in_head(token),
+            Token::EndTag { ref name } if name == "template" => self.in_head(token),
+            Token::StartTag { ref name, .. } => {
+                // The first element decides what kind of content the template has
+                let mode = match &**name {
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => InsertionMode::InTable,
+                    "col" => InsertionMode::InColumnGroup,
+                    "tr" => InsertionMode::InTableBody,
+                    "td" | "th" => InsertionMode::InRow,
+                    _ => InsertionMode::InBody
+                };
+                self.template_modes.pop();
+                self.template_modes.push(mode);
+                self.mode = mode;
+                Step::Reprocess(token)
+            },
+            Token::EndTag { name } => {
+                self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                Step::Done
+            },
+            Token::Eof => {
+                if !self.template_on_stack() {
+                    return Step::Done;
+                }
+                self.error(ParseErrorKind::UnclosedElement("template".to_string()));
+                self.close_template();
+                Step::Reprocess(Token::Eof)
+            }
+        }
+    }
+
+    /// Close the innermost `template` element
+    fn close_template (&mut self) {
+        while IMPLIED_END_TAGS_THOROUGHLY.contains(&self.current_name()) {
+            self.open_elements.pop();
+        }
+        if self.current_name() != "template" {
+            self.error(ParseErrorKind::MisnestedTag("template".to_string()));
+        }
+        self.pop_until(&["template"]);
+        self.clear_active_formatting_to_marker();
+        self.template_modes.pop();
+        self.reset_insertion_mode();
+    }
+
+    fn after_body<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            Token::Comment(data) => {
+                // Comments after the body go into the html element
+                let html = self.open_elements[0];
+                self.insert_comment_in(data, html);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::EndTag { ref name } if name == "html" => {
+                self.mode = InsertionMode::AfterAfterBody;
+                Step::Done
+            },
+            Token::Eof => Step::Done,
+            token => {
+                self.report_unexpected(&token);
+                self.mode = InsertionMode::InBody;
+                Step::Reprocess(token)
+            }
+        }
+    }
+
+    fn in_frameset<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                let whitespace: String = text.chars().filter(|&c| is_html_whitespace(c)).collect();
+                if whitespace.len() != text.len() {
+                    self.error(ParseErrorKind::UnexpectedText);
+                }
+                if !whitespace.is_empty() {
+                    self.insert_text(&whitespace);
+                }
+                Step::Done
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, ref attributes, .. } if name == "frameset" => {
+                self.insert_element(name, attributes);
+                Step::Done
+            },
+            Token::EndTag { ref name } if name == "frameset" => {
+                if self.open_elements.len() == 1 {
+                    self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+                    return Step::Done;
+                }
+                self.open_elements.pop();
+                if self.current_name() != "frameset" {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if name == "frame" => {
+                self.insert_element(name, attributes);
+                self.open_elements.pop();
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "noframes" => self.in_head(token),
+            Token::Eof => {
+                if self.open_elements.len() != 1 {
+                    self.error(ParseErrorKind::UnexpectedEof);
+                }
+                Step::Done
+            },
+            token => {
+                self.report_unexpected(&token);
+                Step::Done
+            }
+        }
+    }
+
+    fn after_frameset<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(_) | Token::Comment(_) => self.in_frameset(token),
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::EndTag { ref name } if name == "html" => {
+                self.mode = InsertionMode::AfterAfterFrameset;
+                Step::Done
+            },
+            Token::StartTag { ref name, .. } if name == "noframes" => self.in_head(token),
+            Token::Eof => Step::Done,
+            token => {
+                self.report_unexpected(&token);
+                Step::Done
+            }
+        }
+    }
+
+    fn after_after_body<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Comment(data) => {
+                let root = self.document.root();
+                self.insert_comment_in(data, root);
+                Step::Done
+            },
+            Token::Doctype { .. } => self.in_body(token),
+            Token::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::Eof => Step::Done,
+            token => {
+                self.report_unexpected(&token);
+                self.mode = InsertionMode::InBody;
+                Step::Reprocess(token)
+            }
+        }
+    }
+
+    fn after_after_frameset<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Comment(data) => {
+                let root = self.document.root();
+                self.insert_comment_in(data, root);
+                Step::Done
+            },
+            Token::Doctype { .. } => self.in_body(token),
+            Token::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, .. } if name == "noframes" => self.in_head(token),
+            Token::Eof => Step::Done,
+            token => {
+                self.report_unexpected(&token);
+                Step::Done
+            }
+        }
+    }
+
+    // Foreign content
+
+    /// Returns true if the token has to be processed by the rules for foreign content rather
+    /// than those of the insertion mode
+    fn is_foreign_token (&self, token: &Token) -> bool {
+        let node = match self.adjusted_current() {
+            Some(node) if self.namespace(node) != Some(Namespace::Html) => node,
+            _ => return false
+        };
+        match *token {
+            Token::Eof => false,
+            Token::Text(_) => !self.is_mathml_text_integration_point(node) && !self.is_html_integration_point(node),
+            Token::StartTag { ref name, .. } => {
+                let mathml_text = self.is_mathml_text_integration_point(node) && name != "mglyph" && name != "malignmark";
+                let svg_in_annotation = name == "svg" && self.namespace(node) == Some(Namespace::MathMl)
+                    && self.local_name(node) == "annotation-xml";
+                !mathml_text && !svg_in_annotation && !self.is_html_integration_point(node)
+            },
+            _ => true
+        }
+    }
+
+    fn foreign_content<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        match token {
+            Token::Text(text) => {
+                let text = text.replace('\0', "\u{fffd}");
+                if !is_whitespace(&text) {
+                    self.frameset_ok = false;
+                }
+                self.insert_text(&text);
+                Step::Done
+            },
+            Token::Comment(data) => {
+                self.insert_comment(data);
+                Step::Done
+            },
+            Token::Doctype { .. } => {
+                self.error(ParseErrorKind::UnexpectedDoctype);
+                Step::Done
+            },
+            Token::StartTag { ref name, ref attributes, .. } if BREAKOUT_ELEMENTS.contains(&&**name)
+                    || (name == "font" && ["color", "face", "size"].iter().any(|&a| attribute(attributes, a).is_some())) => {
+                self.break_out_of_foreign_content(token)
+            },
+            Token::EndTag { ref name } if name == "br" || name == "p" => self.break_out_of_foreign_content(token),
+            Token::StartTag { name, attributes, self_closing } => {
+                let namespace = self.adjusted_current().and_then(|node| self.namespace(node)).unwrap_or(Namespace::Html);
+                self.insert_foreign_element(namespace, &name, attributes, self_closing);
+                Step::Done
+            },
+            Token::EndTag { name } => self.foreign_end_tag(name),
+            Token::Eof => {
+                let mode = self.mode;
+                self.step(mode, Token::Eof)
+            }
+        }
+    }
+
+    /// Close the foreign elements for an html tag that can't be inside of them
+    fn break_out_of_foreign_content<'a> (&mut self, token: Token<'a>) -> Step<'a> {
+        self.report_unexpected(&token);
+        while let Some(current) = self.current() {
+            if self.namespace(current) == Some(Namespace::Html) || self.is_mathml_text_integration_point(current)
+                    || self.is_html_integration_point(current) {
+                break;
+            }
+            self.open_elements.pop();
+        }
+        let mode = self.mode;
+        self.step(mode, token)
+    }
+
+    fn foreign_end_tag<'a> (&mut self, name: Atom) -> Step<'a> {
+        let current = match self.current() {
+            Some(current) => current,
+            None => return Step::Done
+        };
+        if !self.local_name(current).eq_ignore_ascii_case(&name) {
+            self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string()));
+        }
+        // Close the innermost foreign element with that name, unless an html element comes first
+        for position in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[position];
+            if self.namespace(node) == Some(Namespace::Html) {
+                let mode = self.mode;
+                return self.step(mode, Token::EndTag { name });
+            }
+            if position == 0 {
+                break;
+            }
+            if self.local_name(node).eq_ignore_ascii_case(&name) {
+                self.open_elements.truncate(position);
+                break;
+            }
+        }
+        Step::Done
+    }
+
+    /// Insert an svg or mathml element for a start tag, restoring the case of its names
+    fn insert_foreign_element (&mut self, namespace: Namespace, name: &str, mut attributes: AttrMap, self_closing: bool) {
+        let (element_names, attribute_names) = match namespace {
+            Namespace::Svg => (SVG_ELEMENT_NAMES, SVG_ATTRIBUTE_NAMES),
+            Namespace::MathMl => (&[][..], MATHML_ATTRIBUTE_NAMES),
+            _ => (&[][..], &[][..])
+        };
+        let name = element_names.iter()
+            .find(|&&(lowercase, _)| lowercase == name)
+            .map_or(name, |&(_, adjusted)| adjusted);
+        for &(lowercase, adjusted) in attribute_names {
+            if let Some(&mut (ref mut name, _)) = attributes.iter_mut().find(|&&mut (ref name, _)| name == lowercase) {
+                *name = Atom::from(adjusted);
+            }
+        }
+
+        self.insert_element_ns(namespace, name, &attributes);
+        if self_closing {
+            self.open_elements.pop();
+            self.self_closing_acknowledged = true;
+        }
+    }
+
+    fn report_unexpected (&mut self, token: &Token) {
+        match *token {
+            Token::Text(_) => self.error(ParseErrorKind::UnexpectedText),
+            Token::StartTag { ref name, .. } => self.error(ParseErrorKind::UnexpectedStartTag(name.to_string())),
+            Token::EndTag { ref name } => self.error(ParseErrorKind::UnexpectedClosingTag(name.to_string())),
+            Token::Doctype { .. } => self.error(ParseErrorKind::UnexpectedDoctype),
+            Token::Comment(_) | Token::Eof => {}
+        }
+    }
+
+    // The tree and the stack of open elements
+
+    /// The name of an html element, or nothing for other nodes. Most rules only apply to
+    /// html elements, an svg `title` is not a `title`.
+    fn name (&self, node: NodeId) -> &str {
+        match self.element(node) {
+            Some((Namespace::Html, name)) => name,
+            _ => ""
+        }
+    }
+
+    /// The name of an element of any namespace
+    fn local_name (&self, node: NodeId) -> &str {
+        self.element(node).map_or("", |(_, name)| name)
+    }
+
+    fn namespace (&self, node: NodeId) -> Option<Namespace> {
+        self.element(node).map(|(namespace, _)| namespace)
+    }
+
+    /// The namespace and name of an element
+    fn element (&self, node: NodeId) -> Option<(Namespace, &str)> {
+        match self.document[node].node_type {
+            NodeType::Element(ref element) => Some((element.namespace().unwrap_or(Namespace::Html), &element.name.local)),
+            _ => None
+        }
+    }
+
+    fn template_contents (&self, node: NodeId) -> Option<NodeId> {
+        match self.document[node].node_type {
+            NodeType::Element(ref element) => element.template_contents(),
+            _ => None
+        }
+    }
+
+    fn template_on_stack (&self) -> bool {
+        self.open_elements.iter().any(|&node| self.name(node) == "template")
+    }
+
+    fn is_special (&self, node: NodeId) -> bool {
+        SPECIAL_ELEMENTS.contains(&self.name(node)) || self.is_special_foreign(node)
+    }
+
+    /// The foreign elements that are special, and bound most scopes like `td` does
+    fn is_special_foreign (&self, node: NodeId) -> bool {
+        match self.element(node) {
+            Some((Namespace::MathMl, name)) => ["mi", "mo", "mn", "ms", "mtext", "annotation-xml"].contains(&name),
+            Some((Namespace::Svg, name)) => ["foreignObject", "desc", "title"].contains(&name),
+            _ => false
+        }
+    }
+
+    /// Mathml elements whose text content is html
+    fn is_mathml_text_integration_point (&self, node: NodeId) -> bool {
+        match self.element(node) {
+            Some((Namespace::MathMl, name)) => ["mi", "mo", "mn", "ms", "mtext"].contains(&name),
+            _ => false
+        }
+    }
+
+    /// Foreign elements whose content is html
+    fn is_html_integration_point (&self, node: NodeId) -> bool {
+        match self.document[node].node_type {
+            NodeType::Element(ref element) => match element.namespace() {
+                Some(Namespace::MathMl) => {
+                    element.name.local == "annotation-xml" && element.get_attribute("encoding").is_some_and(|encoding| {
+                        encoding.eq_ignore_ascii_case("text/html") || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+                    })
+                },
+                Some(Namespace::Svg) => ["foreignObject", "desc", "title"].contains(&&*element.name.local),
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    fn current (&self) -> Option<NodeId> {
+        self.open_elements.last().cloned()
+    }
+
+    /// The node the tree construction rules look at to decide how to process a token
+    fn adjusted_current (&self) -> Option<NodeId> {
+        match self.context {
+            Some(context) if self.open_elements.len() == 1 => Some(context),
+            _ => self.current()
+        }
+    }
+
+    fn current_name (&self) -> &str {
+        match self.current() {
+            Some(current) => self.name(current),
+            None => ""
+        }
+    }
+
+    fn create_element (&mut self, name: &str, attributes: &AttrMap) -> NodeId {
+        self.create_element_ns(Namespace::Html, name, attributes)
+    }
+
+    fn create_element_ns (&mut self, namespace: Namespace, name: &str, attributes: &AttrMap) -> NodeId {
+        // Only the element of the start tag being processed comes from the source
+        let span = match self.start_tag {
+            Some(ref start_tag) if start_tag.eq_ignore_ascii_case(name) => Some(self.span),
+            _ => None
+        };
+        if span.is_some() {
+            self.start_tag = None;
+        }
+        let element = if namespace == Namespace::Html && name == "template" {
+            self.document.template(attributes.clone(), Vec::new())
+        } else {
+            let name = QualName::new(None, namespace.atom(), Atom::from(name));
+            self.document.elem_ns(name, dom_attributes(namespace, attributes), Vec::new())
+        };
+        self.document.set_span(element, span);
+        element
+    }
+
+    /// Create a new element with the same name and attributes as an existing one
+    fn clone_element (&mut self, node: NodeId) -> NodeId {
+        let (name, attributes) = match self.document[node].node_type {
+            NodeType::Element(ref element) => (element.name.clone(), element.attributes().cloned().collect()),
+            _ => panic!("only elements can be cloned")
+        };
+        self.document.elem_ns(name, attributes, Vec::new())
+    }
+
+    fn merge_attributes (&mut self, node: NodeId, attributes: AttrMap) {
+        for (name, value) in attributes {
+            let missing = match self.document[node].node_type {
+                NodeType::Element(ref element) => element.get_attribute(&name).is_none(),
+                _ => false
+            };
+            if missing {
+                self.document.set_attribute(node, &name, &value);
+            }
+        }
+    }
+
+    fn append (&mut self, parent: NodeId, child: NodeId) {
+        self.insert_at((parent, None), child);
+    }
+
+    /// Insert a node into `parent`, before the child `before` or at the end
+    fn insert_at (&mut self, (parent, before): (NodeId, Option<NodeId>), child: NodeId) {
+        self.document.move_node(parent, child, before);
+    }
+
+    /// The parent and the following sibling a new node has to be inserted at
+    fn appropriate_place (&self, override_target: Option<NodeId>) -> (NodeId, Option<NodeId>) {
+        let target = override_target.or_else(|| self.current()).unwrap_or(self.document.root());
+        if !self.foster_parenting || !["table", "tbody", "tfoot", "thead", "tr"].contains(&self.name(target)) {
+            // Content of a template goes into its fragment
+            return (self.template_contents(target).unwrap_or(target), None);
+        }
+
+        // Foster parenting: content that does not belong in a table goes right before it
+        let table = self.open_elements.iter().rposition(|&node| self.name(node) == "table");
+        let template = self.open_elements.iter().rposition(|&node| self.name(node) == "template");
+        if let Some(template) = template {
+            if table.is_none_or(|table| template > table) {
+                let template = self.open_elements[template];
+                return (self.template_contents(template).unwrap_or(template), None);
+            }
+        }
+        match table {
+            Some(position) => {
+                let table = self.open_elements[position];
+                match self.document[table].parent() {
+                    Some(parent) => (parent, Some(table)),
+                    None => (self.open_elements[position - 1], None)
+                }
+            },
+            None => (self.open_elements[0], None)
+        }
+    }
+
+    /// Insert an element for a start tag at the appropriate place and push it onto the stack
+    fn insert_element (&mut self, name: &str, attributes: &AttrMap) -> NodeId {
+        self.insert_element_ns(Namespace::Html, name, attributes)
+    }
+
+    fn insert_element_ns (&mut self, namespace: Namespace, name: &str, attributes: &AttrMap) -> NodeId {
+        let element = self.create_element_ns(namespace, name, attributes);
+        let place = self.appropriate_place(None);
+        self.insert_at(place, element);
+        self.open_elements.push(element);
+        element
+    }
+
+    /// Insert an element whose content is only text, like `title`, `style` or `textarea`
+    fn insert_text_element (&mut self, name: &str, attributes: &AttrMap) {
+        self.insert_element(name, attributes);
+        self.content_model = Some(match name {
+            "title" | "textarea" => ContentModel::RcData,
+            "script" => ContentModel::ScriptData,
+            _ => ContentModel::RawText
+        });
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    fn insert_text (&mut self, text: &str) {
+        let (parent, before) = self.appropriate_place(None);
+        if parent == self.document.root() {
+            return;
+        }
+
+        // Merge with a directly preceding text node
+        let previous = match before {
+            Some(before) => self.document[before].previous_sibling(),
+            None => self.document[parent].last_child()
+        };
+        if let Some(previous) = previous {
+            if let NodeType::Text(_) = self.document[previous].node_type {
+                self.document.append_text(previous, text);
+                let span = self.document[previous].span.map(|span| Span { end: self.span.end, ..span });
+                self.document.set_span(previous, span);
+                return;
+            }
+        }
+
+        let node = self.document.text(text.to_string());
+        self.document.set_span(node, Some(self.span));
+        self.insert_at((parent, before), node);
+    }
+
+    fn insert_comment (&mut self, data: String) {
+        let place = self.appropriate_place(None);
+        let node = self.document.comment(data);
+        self.document.set_span(node, Some(self.span));
+        self.insert_at(place, node);
+    }
+
+    fn insert_comment_in (&mut self, data: String, parent: NodeId) {
+        let node = self.document.comment(data);
+        self.document.set_span(node, Some(self.span));
+        self.append(parent, node);
+    }
+
+    fn remove_from_stack (&mut self, element: NodeId) {
+        self.open_elements.retain(|&node| node != element);
+    }
+
+    /// Pop elements until one with one of the given names has been popped
+    fn pop_until (&mut self, names: &[&str]) {
+        while let Some(node) = self.open_elements.pop() {
+            if names.contains(&self.name(node)) {
+                break;
+            }
+        }
+    }
+
+    /// Pop elements until the current node has one of the given names or is the html or a
+    /// template element
+    fn clear_stack_to (&mut self, names: &[&str]) {
+        while !names.contains(&self.current_name()) && !["html", "template"].contains(&self.current_name()) {
+            self.open_elements.pop();
+        }
+    }
+
+    fn is_scope_boundary (&self, node: NodeId, scope: Scope) -> bool {
+        let name = self.name(node);
+        match scope {
+            Scope::Select => name != "optgroup" && name != "option",
+            Scope::Table => ["html", "table", "template"].contains(&name),
+            _ => {
+                ["applet", "caption", "html", "table", "td", "th", "marquee", "object", "template"].contains(&name)
+                    || self.is_special_foreign(node)
+                    || (scope == Scope::ListItem && (name == "ol" || name == "ul"))
+                    || (scope == Scope::Button && name == "button")
+            }
+        }
+    }
+
+    /// Returns true if an element with one of the given names is in the given scope
+    fn in_scope (&self, names: &[&str], scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if names.contains(&self.name(node)) {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Returns true if the given element is in the given scope
+    fn node_in_scope (&self, element: NodeId, scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if node == element {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn generate_implied_end_tags (&mut self, except: Option<&str>) {
+        while IMPLIED_END_TAGS.contains(&self.current_name()) && Some(self.current_name()) != except {
+            self.open_elements.pop();
+        }
+    }
+
+    fn close_p (&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        if self.current_name() != "p" {
+            self.error(ParseErrorKind::MisnestedTag("p".to_string()));
+        }
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_in_button_scope (&mut self) {
+        if self.in_scope(&["p"], Scope::Button) {
+            self.close_p();
+        }
+    }
+
+    fn check_unclosed_elements (&mut self) {
+        const MAY_BE_OPEN: &[&str] = &[
+            "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc", "tbody", "td",
+            "tfoot", "th", "thead", "tr", "body", "html"
+        ];
+        let unclosed = self.open_elements.iter()
+            .map(|&node| self.local_name(node))
+            .find(|name| !MAY_BE_OPEN.contains(name))
+            .map(|name| name.to_string());
+        if let Some(name) = unclosed {
+            self.error(ParseErrorKind::UnclosedElement(name.to_string()));
+        }
+    }
+
+    /// Where an element would be reset to after a table or select element ends
+    fn reset_insertion_mode (&mut self) {
+        for (position, &node) in self.open_elements.iter().enumerate().rev() {
+            let last = position == 0;
+            // A fragment is parsed as if its context element was the root
+            let node = if last { self.context.unwrap_or(node) } else { node };
+            self.mode = match self.name(node) {
+                "select" => {
+                    let in_table = self.open_elements[..position].iter().any(|&n| self.name(n) == "table");
+                    if in_table { InsertionMode::InSelectInTable } else { InsertionMode::InSelect }
+                },
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "template" => *self.template_modes.last().unwrap_or(&InsertionMode::InTemplate),
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "frameset" => InsertionMode::InFrameset,
+                "html" => if self.head.is_none() { InsertionMode::BeforeHead } else { InsertionMode::AfterHead },
+                _ if last => InsertionMode::InBody,
+                _ => continue
+            };
+            return;
+        }
+    }
+
+    // The list of active formatting elements
+
+    /// The last formatting element with the given name after the last marker
+    fn active_formatting_element (&self, name: &str) -> Option<NodeId> {
+        for entry in self.active_formatting.iter().rev() {
+            match *entry {
+                FormattingEntry::Marker => return None,
+                FormattingEntry::Element(node) if self.name(node) == name => return Some(node),
+                FormattingEntry::Element(_) => {}
+            }
+        }
+        None
+    }
+
+    fn formatting_position (&self, element: NodeId) -> Option<usize> {
+        self.active_formatting.iter().position(|&entry| entry == FormattingEntry::Element(element))
+    }
+
+    fn remove_from_active_formatting (&mut self, element: NodeId) {
+        self.active_formatting.retain(|&entry| entry != FormattingEntry::Element(element));
+    }
+
+    fn push_active_formatting (&mut self, element: NodeId) {
+        // At most three identical elements are kept after the last marker
+        let same: Vec<NodeId> = self.active_formatting.iter().rev()
+            .take_while(|&&entry| entry != FormattingEntry::Marker)
+            .filter_map(|&entry| match entry {
+                FormattingEntry::Element(node) if self.same_element(node, element) => Some(node),
+                _ => None
+            })
+            .collect();
+        if same.len() >= 3 {
+            self.remove_from_active_formatting(same[same.len() - 1]);
+        }
+        self.active_formatting.push(FormattingEntry::Element(element));
+    }
+
+    fn same_element (&self, a: NodeId, b: NodeId) -> bool {
+        match (&self.document[a].node_type, &self.document[b].node_type) {
+            // Attributes are compared regardless of their order
+            (NodeType::Element(a), NodeType::Element(b)) => a.name == b.name && a.attributes().count() == b.attributes().count()
+                && a.attributes().all(|attribute| b.attributes().any(|other| attribute == other)),
+            _ => false
+        }
+    }
+
+    fn clear_active_formatting_to_marker (&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if entry == FormattingEntry::Marker {
+                break;
+            }
+        }
+    }
+
+    /// Reopen formatting elements that were closed implicitly, e.g. the `b` in `<p><b>x<p>y`
+    fn reconstruct_active_formatting (&mut self) {
+        let open = |builder: &TreeBuilder, entry: FormattingEntry| match entry {
+            FormattingEntry::Marker => true,
+            FormattingEntry::Element(node) => builder.open_elements.contains(&node)
+        };
+
+        let mut index = match self.active_formatting.last() {
+            Some(&entry) if !open(self, entry) => self.active_formatting.len() - 1,
+            _ => return
+        };
+        while index > 0 && !open(self, self.active_formatting[index - 1]) {
+            index -= 1;
+        }
+
+        for i in index..self.active_formatting.len() {
+            if let FormattingEntry::Element(node) = self.active_formatting[i] {
+                let element = self.clone_element(node);
+                let place = self.appropriate_place(None);
+                self.insert_at(place, element);
+                self.open_elements.push(element);
+                self.active_formatting[i] = FormattingEntry::Element(element);
+            }
+        }
+    }
+
+    /// The adoption agency algorithm for misnested formatting elements. Returns false if the
+    /// end tag has to be handled like any other end tag instead.
+    fn adoption_agency (&mut self, subject: &str) -> bool {
+        if self.current_name() == subject {
+            let current = self.current().unwrap();
+            if self.formatting_position(current).is_none() {
+                self.open_elements.pop();
+                return true;
+            }
+        }
+
+        for _ in 0..8 {
+            let formatting_element = match self.active_formatting_element(subject) {
+                Some(element) => element,
+                None => return false
+            };
+
+            let stack_position = match self.open_elements.iter().position(|&node| node == formatting_element) {
+                Some(position) => position,
+                None => {
+                    self.error(ParseErrorKind::MisnestedTag(subject.to_string()));
+                    self.remove_from_active_formatting(formatting_element);
+                    return true;
+                }
+            };
+            if !self.node_in_scope(formatting_element, Scope::Default) {
+                self.error(ParseErrorKind::MisnestedTag(subject.to_string()));
+                return true;
+            }
+            if self.current() != Some(formatting_element) {
+                self.error(ParseErrorKind::MisnestedTag(subject.to_string()));
+            }
+
+            let furthest_block = self.open_elements[stack_position + 1..].iter()
+                .cloned()
+                .find(|&node| self.is_special(node));
+            let furthest_block = match furthest_block {
+                Some(block) => block,
+                None => {
+                    self.open_elements.truncate(stack_position);
+                    self.remove_from_active_formatting(formatting_element);
+                    return true;
+                }
+            };
+
+            let common_ancestor = self.open_elements[stack_position - 1];
+            let mut bookmark = self.formatting_position(formatting_element).unwrap();
+
+            let mut node_position = self.open_elements.iter().position(|&node| node == furthest_block).unwrap();
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_position -= 1;
+                let node = self.open_elements[node_position];
+                if node == formatting_element {
+                    break;
+                }
+
+                if inner_loop_counter > 3 && self.formatting_position(node).is_some() {
+                    let position = self.formatting_position(node).unwrap();
+                    if position < bookmark {
+                        bookmark -= 1;
+                    }
+                    self.active_formatting.remove(position);
+                }
+                let formatting_position = match self.formatting_position(node) {
+                    Some(position) => position,
+                    None => {
+                        self.open_elements.remove(node_position);
+                        continue;
+                    }
+                };
+
+                let new_node = self.clone_element(node);
+                self.active_formatting[formatting_position] = FormattingEntry::Element(new_node);
+                self.open_elements[node_position] = new_node;
+                if last_node == furthest_block {
+                    bookmark = formatting_position + 1;
+                }
+                self.append(new_node, last_node);
+                last_node = new_node;
+            }
+
+            let place = self.appropriate_place(Some(common_ancestor));
+            self.insert_at(place, last_node);
+
+            let new_element = self.clone_element(formatting_element);
+            while let Some(child) = self.document[furthest_block].first_child() {
+                self.append(new_element, child);
+            }
+            self.append(furthest_block, new_element);
+
+            let old_position = self.formatting_position(formatting_element).unwrap();
+            self.active_formatting[old_position] = FormattingEntry::Element(new_element);
+            // Move the entry to the bookmark
+            let entry = self.active_formatting.remove(old_position);
+            let bookmark = if old_position < bookmark { bookmark - 1 } else { bookmark };
+            self.active_formatting.insert(bookmark.min(self.active_formatting.len()), entry);
+
+            self.remove_from_stack(formatting_element);
+            let block_position = self.open_elements.iter().position(|&node| node == furthest_block).unwrap();
+            self.open_elements.insert(block_position + 1, new_element);
+        }
+        true
+    }
+}
+
+/// Let elements without a closing tag end with their content, like the `p` in `<p>Text`
+fn end_spans (document: &mut Document) {
+    let mut roots = vec![document.root()];
+    while let Some(root) = roots.pop() {
+        let nodes: Vec<NodeId> = document.descendants_post_order(root).elements().collect();
+        for node in nodes {
+            if let NodeType::Element(ref element) = document[node].node_type {
+                roots.extend(element.template_contents());
+            }
+            let end = document.children(node).filter_map(|child| document[child].span).map(|child| child.end).max();
+            let span = document[node].span.map(|span| Span { end: end.map_or(span.end, |end| end.max(span.end)), ..span });
+            document.set_span(node, span);
+        }
+    }
+}
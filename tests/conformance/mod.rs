@@ -0,0 +1,64 @@
+//! Counts the results of the html5lib-tests runners and compares the failures with a baseline,
+//! so that the upstream tests can be run before all of them pass.
+//!
+//! The baseline is a file with the id of every test that is known to fail, one per line. A run
+//! fails if a test fails that isn't in the baseline, or if a test in the baseline passes. Run
+//! with `HTML5LIB_BLESS=1` to write the current failures to the baseline instead.
+//!
+//! The upstream commit the vendored files were taken from is recorded in `REVISION`, next to
+//! the baselines.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct Results {
+    pub passed: usize,
+    /// The id and a description of every test that failed
+    pub failed: Vec<(String, String)>,
+    pub skipped: usize
+}
+
+impl Results {
+    pub fn fail (&mut self, id: String, description: String) {
+        self.failed.push((id, description));
+    }
+}
+
+/// Print the counts of `results` and check its failures against the baseline at `path`
+pub fn check (name: &str, results: &Results, path: &Path) {
+    let failed: BTreeSet<&str> = results.failed.iter().map(|(id, _)| &**id).collect();
+    if env::var_os("HTML5LIB_BLESS").is_some() {
+        let mut baseline = String::from("# Tests that are known to fail, written with HTML5LIB_BLESS=1\n");
+        for id in &failed {
+            baseline.push_str(id);
+            baseline.push('\n');
+        }
+        fs::write(path, baseline).unwrap();
+    }
+
+    let source = fs::read_to_string(path).unwrap_or_default();
+    let baseline: BTreeSet<&str> = source.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let unexpected: Vec<_> = results.failed.iter().filter(|(id, _)| !baseline.contains(&**id)).collect();
+    let fixed: Vec<_> = baseline.difference(&failed).collect();
+
+    for (id, description) in &unexpected {
+        println!("FAIL {}: {}", id, description);
+    }
+    for id in &fixed {
+        println!("PASS {} (in the baseline)", id);
+    }
+    match fs::read_to_string(path.with_file_name("REVISION")) {
+        Ok(revision) => println!("{} at html5lib-tests {}", name, revision.trim()),
+        Err(_) => println!("{}: the upstream html5lib-tests are not vendored, only our own tests were run", name)
+    }
+    println!("{}: {} passed, {} failed ({} in the baseline), {} skipped",
+             name, results.passed, results.failed.len(), results.failed.len() - unexpected.len(), results.skipped);
+    assert!(unexpected.is_empty() && fixed.is_empty(),
+            "{} tests failed and {} tests in {} passed, run with HTML5LIB_BLESS=1 to update it",
+            unexpected.len(), fixed.len(), path.display());
+}
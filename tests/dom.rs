@@ -0,0 +1,495 @@
+extern crate rbe;
+
+use rbe::atom::Atom;
+use rbe::dom::{ AttrMap, Doctype, Document, MutationRecord, Namespace, NodeType, QualName, QuirksMode };
+use rbe::html;
+use rbe::parser::{ SelectorError, SelectorErrorKind };
+use rbe::serializer;
+
+#[test]
+fn links_parents_and_siblings () {
+    let mut document = Document::new();
+    let a = document.text("a".to_string());
+    let b = document.elem(Atom::from("b"), AttrMap::new(), Vec::new());
+    let c = document.comment("c".to_string());
+    let p = document.elem(Atom::from("p"), AttrMap::new(), vec![a, b, c]);
+
+    assert_eq!(document.children(p).collect::<Vec<_>>(), vec![a, b, c]);
+    assert_eq!(document[p].first_child(), Some(a));
+    assert_eq!(document[p].last_child(), Some(c));
+    assert_eq!(document[b].parent(), Some(p));
+    assert_eq!(document[b].previous_sibling(), Some(a));
+    assert_eq!(document[b].next_sibling(), Some(c));
+    assert_eq!(document[a].previous_sibling(), None);
+    assert_eq!(document[c].next_sibling(), None);
+    assert_eq!(document[p].parent(), None);
+}
+
+#[test]
+fn finds_the_enclosing_link () {
+    let document = html::parse("<p><a href=x><b>bold</b></a>".to_string()).document;
+    let mut node = document.document_element();
+    while let Some(id) = node {
+        if let NodeType::Text(_) = document[id].node_type {
+            break;
+        }
+        node = document[id].first_child().or(document[id].next_sibling());
+    }
+    let text = node.unwrap();
+
+    let mut ancestor = document[text].parent();
+    while let Some(id) = ancestor {
+        match document[id].node_type {
+            NodeType::Element(ref element) if element.name.local == "a" => break,
+            _ => ancestor = document[id].parent()
+        }
+    }
+    let link = ancestor.unwrap();
+    assert_eq!(document.outer_html(link), "<a href=\"x\"><b>bold</b></a>");
+    assert_eq!(document[document.root()].first_child(), document.document_element());
+}
+
+#[test]
+fn inserts_moves_and_removes_children () {
+    let mut document = Document::new();
+    let a = document.elem(Atom::from("a"), AttrMap::new(), Vec::new());
+    let b = document.elem(Atom::from("b"), AttrMap::new(), Vec::new());
+    let c = document.elem(Atom::from("c"), AttrMap::new(), Vec::new());
+    let p = document.elem(Atom::from("p"), AttrMap::new(), Vec::new());
+
+    document.append_child(p, a);
+    document.append_child(p, c);
+    document.insert_before(p, b, Some(c));
+    assert_eq!(document.outer_html(p), "<p><a></a><b></b><c></c></p>");
+
+    // Adding a node that is already in the tree moves it
+    document.insert_before(p, c, Some(a));
+    assert_eq!(document.outer_html(p), "<p><c></c><a></a><b></b></p>");
+    document.append_child(a, b);
+    assert_eq!(document.outer_html(p), "<p><c></c><a><b></b></a></p>");
+    assert_eq!(document[a].next_sibling(), None);
+    assert_eq!(document[p].last_child(), Some(a));
+
+    document.remove_child(p, c);
+    assert_eq!(document.outer_html(p), "<p><a><b></b></a></p>");
+    assert_eq!(document[c].parent(), None);
+    assert_eq!(document[a].previous_sibling(), None);
+
+    document.replace_child(p, c, a);
+    assert_eq!(document.outer_html(p), "<p><c></c></p>");
+    assert_eq!(document[a].parent(), None);
+    assert_eq!(document.outer_html(a), "<a><b></b></a>");
+}
+
+#[test]
+fn clones_nodes_and_template_contents () {
+    let mut document = html::parse("<template id=t><li class=a>Hi <b>there</b></template><ul></ul>".to_string()).document;
+    let template = document.get_element_by_id("t").unwrap();
+    let contents = match document[template].node_type {
+        NodeType::Element(ref element) => element.template_contents().unwrap(),
+        _ => unreachable!()
+    };
+    let list = document.query_selector(document.root(), "ul").unwrap().unwrap();
+
+    // The contents stay in the template however often they are cloned
+    for _ in 0..2 {
+        let copy = document.clone_node(contents, true);
+        document.append_child(list, copy);
+    }
+    assert_eq!(document.inner_html(list), "<li class=\"a\">Hi <b>there</b></li><li class=\"a\">Hi <b>there</b></li>");
+    assert_eq!(document.inner_html(template), "<li class=\"a\">Hi <b>there</b></li>");
+
+    let shallow = document.clone_node(list, false);
+    assert_eq!(document.outer_html(shallow), "<ul></ul>");
+    assert_eq!(document[shallow].parent(), None);
+
+    let copy = document.clone_node(template, true);
+    assert_eq!(document.outer_html(copy), "<template id=\"t\"><li class=\"a\">Hi <b>there</b></li></template>");
+    let b = document.descendants(contents).elements().nth(1).unwrap();
+    document.set_text(b, "you");
+    assert_eq!(document.inner_html(template), "<li class=\"a\">Hi <b>you</b></li>");
+    assert_eq!(document.inner_html(copy), "<li class=\"a\">Hi <b>there</b></li>");
+    let shallow = document.clone_node(template, false);
+    assert_eq!(document.outer_html(shallow), "<template id=\"t\"></template>");
+}
+
+#[test]
+#[should_panic]
+fn refuses_to_add_a_node_to_itself () {
+    let mut document = Document::new();
+    let b = document.elem(Atom::from("b"), AttrMap::new(), Vec::new());
+    let p = document.elem(Atom::from("p"), AttrMap::new(), vec![b]);
+    document.append_child(b, p);
+}
+
+#[test]
+#[should_panic(expected = "only documents, fragments and elements can have children")]
+fn refuses_children_of_text () {
+    let mut document = Document::new();
+    let text = document.text("a".to_string());
+    let b = document.elem(Atom::from("b"), AttrMap::new(), Vec::new());
+    document.append_child(text, b);
+}
+
+#[test]
+#[should_panic(expected = "the document can't have text as a child")]
+fn refuses_text_in_the_document () {
+    let mut document = html::parse("<p>".to_string()).document;
+    let text = document.text("stray".to_string());
+    let root = document.root();
+    document.append_child(root, text);
+}
+
+#[test]
+#[should_panic(expected = "the document can only have one element as a child")]
+fn refuses_a_second_document_element () {
+    let mut document = html::parse("<p>".to_string()).document;
+    let x = document.elem(Atom::from("x"), AttrMap::new(), Vec::new());
+    let fragment = document.fragment(vec![x]);
+    let root = document.root();
+    document.insert_before(root, fragment, None);
+}
+
+#[test]
+fn replaces_the_document_element () {
+    let mut document = html::parse("<!--a--><p>".to_string()).document;
+    let root = document.root();
+    let html = document.document_element().unwrap();
+    let x = document.elem(Atom::from("x"), AttrMap::new(), Vec::new());
+    document.replace_child(root, x, html);
+    assert_eq!(document.document_element(), Some(x));
+
+    // Moving the document element within the document is fine, and so is a comment
+    let comment = document[root].first_child().unwrap();
+    document.insert_before(root, x, Some(comment));
+    document.append_child(root, comment);
+    assert_eq!(document.inner_html(root), "<x></x><!--a-->");
+    document.set_text(root, "text");
+    assert_eq!(document.inner_html(root), "<x></x><!--a-->");
+}
+
+#[test]
+fn sets_text_and_attributes () {
+    let mut document = html::parse("<p id=x>a<b>b</b><!--c-->".to_string()).document;
+    let body = document[document.document_element().unwrap()].last_child().unwrap();
+    let p = document[body].first_child().unwrap();
+    let comment = document[p].last_child().unwrap();
+
+    document.set_text(comment, "d");
+    assert_eq!(document.inner_html(p), "a<b>b</b><!--d-->");
+    document.set_text(p, "1 < 2");
+    assert_eq!(document.inner_html(p), "1 &lt; 2");
+    document.set_text(p, "");
+    assert_eq!(document[p].first_child(), None);
+
+    document.set_attribute(p, "title", "t");
+    assert_eq!(document.remove_attribute(p, "id"), Some("x".to_string()));
+    assert_eq!(document.remove_attribute(p, "id"), None);
+    assert_eq!(document.outer_html(p), "<p title=\"t\"></p>");
+}
+
+#[test]
+fn edits_the_class_list () {
+    let mut document = html::parse("<p class=' a\tb  a\n'>".to_string()).document;
+    let body = document[document.document_element().unwrap()].last_child().unwrap();
+    let p = document[body].first_child().unwrap();
+    {
+        let mut classes = document.class_list(p);
+        assert!(classes.contains("a") && classes.contains("b") && !classes.contains(""));
+        classes.add("c");
+        classes.remove("a");
+        assert!(!classes.toggle("b"));
+        assert!(classes.toggle("d"));
+    }
+    assert_eq!(document.outer_html(p), "<p class=\"c d\"></p>");
+
+    document.remove_attribute(p, "class");
+    document.class_list(p).remove("c");
+    assert_eq!(document.outer_html(p), "<p></p>");
+}
+
+#[test]
+fn queries_selectors_in_document_order () {
+    let document = html::parse("<div id=main><p class='a b'>1<p class=b>2</div><ul><li class=a>3<li>4</ul>".to_string()).document;
+    let root = document.root();
+    let html = |nodes: Vec<_>| nodes.into_iter().map(|id| document.outer_html(id)).collect::<Vec<_>>();
+
+    assert_eq!(html(document.query_selector_all(root, "li, .b").unwrap()),
+               vec!["<p class=\"a b\">1</p>", "<p class=\"b\">2</p>", "<li class=\"a\">3</li>", "<li>4</li>"]);
+    assert_eq!(html(document.query_selector_all(root, "p.a.b").unwrap()), vec!["<p class=\"a b\">1</p>"]);
+    assert_eq!(document.query_selector_all(root, "span"), Ok(vec![]));
+
+    let main = document.query_selector(root, "#main").unwrap().unwrap();
+    assert_eq!(html(document.query_selector(main, ".a").unwrap().into_iter().collect()), vec!["<p class=\"a b\">1</p>"]);
+    assert_eq!(document.query_selector(main, "div"), Ok(None));
+    assert_eq!(document.query_selector_all(main, "*").unwrap().len(), 2);
+}
+
+#[test]
+fn rejects_selectors_that_cant_be_parsed () {
+    let document = html::parse("<div><p><a href=x>a</a></p></div>".to_string()).document;
+    let root = document.root();
+    let error = |selectors| match document.query_selector_all(root, selectors) {
+        Err(SelectorError { kind, offset }) => (kind, offset),
+        Ok(nodes) => panic!("{:?} matched {:?}", selectors, nodes)
+    };
+
+    assert_eq!(error(""), (SelectorErrorKind::EmptySelector, 0));
+    assert_eq!(error("  "), (SelectorErrorKind::EmptySelector, 2));
+    assert_eq!(error("p,"), (SelectorErrorKind::EmptySelector, 2));
+    assert_eq!(error(", p"), (SelectorErrorKind::EmptySelector, 0));
+    assert_eq!(error("p, ,a"), (SelectorErrorKind::EmptySelector, 3));
+    // Combinators, attribute selectors and pseudo-classes aren't supported
+    assert_eq!(error("div p"), (SelectorErrorKind::UnexpectedCharacter('p'), 4));
+    assert_eq!(error("div > p"), (SelectorErrorKind::UnexpectedCharacter('>'), 4));
+    assert_eq!(error("a[href]"), (SelectorErrorKind::UnexpectedCharacter('['), 1));
+    assert_eq!(error("p:first-child"), (SelectorErrorKind::UnexpectedCharacter(':'), 1));
+    assert_eq!(error("p { color: red }"), (SelectorErrorKind::UnexpectedCharacter('{'), 2));
+    assert_eq!(error("p.#"), (SelectorErrorKind::UnexpectedCharacter('#'), 2));
+    assert_eq!(error("#"), (SelectorErrorKind::UnexpectedEof, 1));
+    // There is no `@namespace` to declare prefixes with
+    assert_eq!(error("svg|a"), (SelectorErrorKind::UndeclaredNamespacePrefix("svg".to_string()), 0));
+    assert_eq!(document.query_selector(root, "a,").unwrap_err().kind, SelectorErrorKind::EmptySelector);
+
+    assert_eq!(document.query_selector_all(root, " p , *|a ").unwrap().len(), 2);
+}
+
+#[test]
+fn keeps_document_information () {
+    let mut document = html::parse("<!DOCTYPE html><title>\n A \t title </title><p>x".to_string()).document;
+    assert_eq!(document.doctype().map(|doctype| &*doctype.name), Some("html"));
+    assert_eq!(document.quirks_mode(), QuirksMode::NoQuirks);
+    assert_eq!(document.title(), "A title");
+    assert_eq!(document.url(), None);
+    document.set_url("http://example.com/".to_string());
+    assert_eq!(document.url(), Some("http://example.com/"));
+
+    let head = document.head().unwrap();
+    let body = document.body().unwrap();
+    assert_eq!(document.outer_html(head), "<head><title>\n A \t title </title></head>");
+    assert_eq!(document.outer_html(body), "<body><p>x</p></body>");
+
+    let document = html::parse("<frameset></frameset>".to_string()).document;
+    assert_eq!(document.quirks_mode(), QuirksMode::Quirks);
+    assert_eq!(document.title(), "");
+    assert_eq!(document.outer_html(document.body().unwrap()), "<frameset></frameset>");
+}
+
+#[test]
+fn keeps_the_doctype_in_document_order () {
+    let mut document = html::parse("<!-- c --><!DOCTYPE html><p>".to_string()).document;
+    let root = document.root();
+    let comment = document[root].first_child().unwrap();
+    let doctype = document[comment].next_sibling().unwrap();
+    assert!(matches!(document[doctype].node_type, NodeType::Doctype(_)));
+    assert_eq!(serializer::serialize_document(&document), "<!-- c --><!DOCTYPE html><html><head></head><body><p></p></body></html>");
+
+    document.set_doctype(None);
+    assert_eq!(document.doctype(), None);
+    document.remove_child(root, comment);
+    document.set_doctype(Some(Doctype { name: "html".to_string(), public_id: None, system_id: None }));
+    assert_eq!(document.doctype().map(|doctype| &*doctype.name), Some("html"));
+    assert_eq!(serializer::serialize_document(&document), "<!DOCTYPE html><html><head></head><body><p></p></body></html>");
+}
+
+#[test]
+#[should_panic(expected = "the doctype of a document has to come before its element")]
+fn refuses_a_doctype_after_the_document_element () {
+    let mut document = html::parse("<p>".to_string()).document;
+    let doctype = document.doctype_node(Doctype { name: "html".to_string(), public_id: None, system_id: None });
+    let root = document.root();
+    document.append_child(root, doctype);
+}
+
+#[test]
+#[should_panic(expected = "the document can only have one doctype as a child")]
+fn refuses_a_second_doctype () {
+    let mut document = html::parse("<!DOCTYPE html>".to_string()).document;
+    let doctype = document.doctype_node(Doctype { name: "html".to_string(), public_id: None, system_id: None });
+    let root = document.root();
+    let first = document[root].first_child();
+    document.insert_before(root, doctype, first);
+}
+
+#[test]
+fn decides_the_quirks_mode_from_the_doctype () {
+    let quirks_mode = |doctype: &str| html::parse(doctype.to_string()).document.quirks_mode();
+    assert_eq!(quirks_mode("<!DOCTYPE HTML>"), QuirksMode::NoQuirks);
+    assert_eq!(quirks_mode("<!DOCTYPE html SYSTEM \"about:legacy-compat\">"), QuirksMode::NoQuirks);
+    assert_eq!(quirks_mode("<!DOCTYPE svg>"), QuirksMode::Quirks);
+    assert_eq!(quirks_mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 3.2 Final//EN\">"), QuirksMode::Quirks);
+    assert_eq!(quirks_mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">"), QuirksMode::Quirks);
+    assert_eq!(quirks_mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">"),
+               QuirksMode::LimitedQuirks);
+    assert_eq!(quirks_mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">"),
+               QuirksMode::NoQuirks);
+
+    // Quirks mode keeps tables inside paragraphs
+    let body = |source: &str| {
+        let document = html::parse(source.to_string()).document;
+        document.inner_html(document.body().unwrap())
+    };
+    assert_eq!(body("<p><table></table>"), "<p><table></table></p>");
+    assert_eq!(body("<!DOCTYPE html><p><table></table>"), "<p></p><table></table>");
+}
+
+#[test]
+fn keeps_the_id_index_in_sync () {
+    let mut document = html::parse("<p id=a>1</p><p id=b>2</p><template><p id=c></p></template>".to_string()).document;
+    let a = document.get_element_by_id("a").unwrap();
+    let b = document.get_element_by_id("b").unwrap();
+    assert_eq!(document.outer_html(a), "<p id=\"a\">1</p>");
+    assert_eq!(document.get_element_by_id("c"), None);
+
+    // The first element in document order wins
+    document.set_attribute(b, "id", "a");
+    assert_eq!(document.get_element_by_id("a"), Some(a));
+    assert_eq!(document.get_element_by_id("b"), None);
+    let body = document.body().unwrap();
+    document.insert_before(body, b, Some(a));
+    assert_eq!(document.get_element_by_id("a"), Some(b));
+
+    // Elements outside of the tree can't be found
+    document.remove_child(body, b);
+    assert_eq!(document.get_element_by_id("a"), Some(a));
+    let div = document.elem(Atom::from("div"), AttrMap::new(), vec![b]);
+    document.set_attribute(div, "id", "d");
+    assert_eq!(document.get_element_by_id("d"), None);
+    document.append_child(body, div);
+    assert_eq!(document.get_element_by_id("d"), Some(div));
+    document.set_text(body, "");
+    assert_eq!(document.get_element_by_id("a"), None);
+    assert_eq!(document.get_element_by_id("d"), None);
+}
+
+#[test]
+fn walks_the_tree () {
+    let document = html::parse("<div id=d><p>a<b>b</b></p>c<!--x--><i>d</i></div>".to_string()).document;
+    let div = document.get_element_by_id("d").unwrap();
+    let name = |id| match document[id].node_type {
+        NodeType::Element(ref element) => element.name.local.to_string(),
+        NodeType::Text(ref data) => data.clone(),
+        NodeType::Comment(_) => "#comment".to_string(),
+        _ => "#document".to_string()
+    };
+    let names = |nodes: Vec<_>| nodes.into_iter().map(name).collect::<Vec<_>>().join(" ");
+
+    assert_eq!(names(document.descendants(div).collect()), "p a b b c #comment i d");
+    assert_eq!(names(document.descendants_post_order(div).collect()), "a b b p c #comment d i");
+    assert_eq!(names(document.descendants(div).elements().collect()), "p b i");
+    assert_eq!(names(document.descendants_post_order(div).elements().collect()), "b p i");
+    assert_eq!(names(document.children(div).elements().collect()), "p i");
+
+    let b = document.query_selector(div, "b").unwrap().unwrap();
+    assert_eq!(names(document.ancestors(b).collect()), "p div body html #document");
+    assert_eq!(names(document.ancestors(b).elements().collect()), "p div body html");
+    assert_eq!(document.descendants(b).count(), 1);
+    let text = document[b].first_child().unwrap();
+    assert_eq!(document.descendants(text).count(), 0);
+    assert_eq!(document.descendants_post_order(text).count(), 0);
+
+    assert_eq!(document.text_content(div), "abcd");
+    assert_eq!(document.text_content(text), "b");
+    assert_eq!(document.text_content(document.root()), "abcd");
+}
+
+#[test]
+fn lists_attributes_in_source_order () {
+    let document = html::parse("<p id=x title=t class=c data-a=1>".to_string()).document;
+    let p = document.get_element_by_id("x").unwrap();
+    if let NodeType::Element(ref element) = document[p].node_type {
+        assert_eq!(element.attributes().map(|attribute| (&*attribute.name.local, &*attribute.value)).collect::<Vec<_>>(),
+                   vec![("id", "x"), ("title", "t"), ("class", "c"), ("data-a", "1")]);
+        assert_eq!(element.get_attribute("title"), Some("t"));
+        assert_eq!(element.get_attribute("lang"), None);
+    }
+}
+
+#[test]
+fn records_mutations_for_observers () {
+    let mut document = html::parse("<p id=p class=a>x</p><i>y</i>".to_string()).document;
+    let p = document.get_element_by_id("p").unwrap();
+    let x = document[p].first_child().unwrap();
+    let i = document[p].next_sibling().unwrap();
+    let body = document.body().unwrap();
+
+    // Changes before observing are not recorded
+    document.set_attribute(p, "title", "t");
+    let observer = document.observe();
+    assert_eq!(document.take_records(observer), vec![]);
+
+    document.set_attribute(p, "title", "u");
+    document.class_list(p).add("b");
+    document.remove_attribute(p, "lang");
+    document.set_text(x, "z");
+    assert_eq!(document.take_records(observer), vec![
+        MutationRecord::Attributes { target: p, name: QualName::from("title"), old_value: Some("t".to_string()) },
+        MutationRecord::Attributes { target: p, name: QualName::from("class"), old_value: Some("a".to_string()) },
+        MutationRecord::CharacterData { target: x, old_value: "x".to_string() },
+    ]);
+    assert_eq!(document.take_records(observer), vec![]);
+
+    // Moving a node removes it first
+    let second = document.observe();
+    document.insert_before(body, i, Some(p));
+    let moved = vec![
+        MutationRecord::ChildList { target: body, added: vec![], removed: vec![i], previous_sibling: Some(p), next_sibling: None },
+        MutationRecord::ChildList { target: body, added: vec![i], removed: vec![], previous_sibling: None, next_sibling: Some(p) },
+    ];
+    assert_eq!(document.take_records(observer), moved);
+    assert_eq!(document.take_records(second), moved);
+
+    document.disconnect(second);
+    document.replace_child(body, x, i);
+    document.set_text(p, "w");
+    let w = document[p].first_child().unwrap();
+    assert_eq!(document.take_records(observer), vec![
+        MutationRecord::ChildList { target: p, added: vec![], removed: vec![x], previous_sibling: None, next_sibling: None },
+        MutationRecord::ChildList { target: body, added: vec![x], removed: vec![i], previous_sibling: None, next_sibling: Some(p) },
+        MutationRecord::ChildList { target: p, added: vec![w], removed: vec![], previous_sibling: None, next_sibling: None },
+    ]);
+    assert_eq!(document.take_records(second), vec![]);
+}
+
+#[test]
+fn qualifies_names_with_namespaces () {
+    let mut document = html::parse("<p xlink:href=a><svg xlink:href=b xml:lang=en xmlns:xlink=c><title>t</title></svg><math><title>".to_string()).document;
+    let root = document.root();
+    let p = document.query_selector(root, "p").unwrap().unwrap();
+    let svg = document.query_selector(root, "svg").unwrap().unwrap();
+    let titles = document.query_selector_all(root, "title").unwrap();
+    assert_eq!(titles.len(), 2);
+
+    match document[p].node_type {
+        NodeType::Element(ref element) => {
+            assert_eq!(element.namespace(), Some(Namespace::Html));
+            assert_eq!(element.get_attribute("xlink:href"), Some("a"));
+        },
+        _ => unreachable!()
+    }
+    match document[svg].node_type {
+        NodeType::Element(ref element) => {
+            assert_eq!(element.name, QualName::new(None, Namespace::Svg.atom(), Atom::from("svg")));
+            assert_eq!(element.get_attribute("xlink:href"), None);
+            assert_eq!(element.get_attribute_ns(Namespace::XLink.url(), "href"), Some("b"));
+            let names: Vec<_> = element.attributes().map(|attribute| attribute.name.to_string()).collect();
+            assert_eq!(names, vec!["xlink:href", "xml:lang", "xmlns:xlink"]);
+            let xml = element.attributes().nth(1).unwrap();
+            assert_eq!((&xml.name.prefix, &xml.name.ns, &xml.name.local), (&Some(Atom::from("xml")), &Namespace::Xml.atom(), &Atom::from("lang")));
+        },
+        _ => unreachable!()
+    }
+    let namespaces: Vec<_> = titles.iter().map(|&title| match document[title].node_type {
+        NodeType::Element(ref element) => element.namespace(),
+        _ => None
+    }).collect();
+    assert_eq!(namespaces, vec![Some(Namespace::Svg), Some(Namespace::MathMl)]);
+    assert_eq!(document.outer_html(svg), "<svg xlink:href=\"b\" xml:lang=\"en\" xmlns:xlink=\"c\"><title>t</title></svg>");
+
+    // Attributes in different namespaces are kept apart
+    let xlink = QualName::new(Some(Atom::from("xlink")), Namespace::XLink.atom(), Atom::from("href"));
+    document.set_attribute(svg, "href", "d");
+    document.set_attribute_ns(svg, xlink, "e");
+    assert_eq!(document.remove_attribute_ns(svg, Namespace::XLink.url(), "href"), Some("e".to_string()));
+    assert_eq!(document.remove_attribute(svg, "href"), Some("d".to_string()));
+}
@@ -0,0 +1,132 @@
+extern crate rbe;
+
+use rbe::encoding::{ self, Encoding, Decoder };
+use rbe::html;
+
+#[test]
+fn sniffs_byte_order_marks () {
+    assert_eq!(encoding::sniff(b"\xef\xbb\xbf<p>", None), (Encoding::Utf8, 3));
+    assert_eq!(encoding::sniff(b"\xfe\xff\x00<", None), (Encoding::Utf16Be, 2));
+    assert_eq!(encoding::sniff(b"\xff\xfe<\x00", None), (Encoding::Utf16Le, 2));
+    // A byte order mark beats the transport layer and `<meta>`
+    assert_eq!(encoding::sniff(b"\xef\xbb\xbf<meta charset=latin1>", Some("utf-16be")), (Encoding::Utf8, 3));
+    assert_eq!(encoding::sniff(b"<p>", None), (Encoding::Windows1252, 0));
+}
+
+#[test]
+fn prefers_the_transport_charset_to_meta () {
+    let source = b"<meta charset=utf-8><p>\xe9";
+    assert_eq!(encoding::sniff(source, Some("ISO-8859-1")), (Encoding::Windows1252, 0));
+    assert_eq!(encoding::sniff(source, None), (Encoding::Utf8, 0));
+    // Unknown labels are ignored
+    assert_eq!(encoding::sniff(source, Some("x-unknown")), (Encoding::Utf8, 0));
+
+    let result = html::parse_bytes(source, Some(" latin1 "));
+    assert_eq!(result.encoding, Encoding::Windows1252);
+    let p = result.document.query_selector(result.document.root(), "p").unwrap().unwrap();
+    assert_eq!(result.document.text_content(p), "\u{e9}");
+}
+
+#[test]
+fn prescans_meta_tags () {
+    assert_eq!(encoding::prescan(b"<meta charset=\"UTF-8\">"), Some(Encoding::Utf8));
+    assert_eq!(encoding::prescan(b"<META CHARSET='latin1'/>"), Some(Encoding::Windows1252));
+    assert_eq!(encoding::prescan(b"<meta http-equiv=Content-Type content=\"text/html; charset=utf-8\">"), Some(Encoding::Utf8));
+    assert_eq!(encoding::prescan(b"<meta content='text/html;charset = \"utf-8\"' http-equiv='content-type'>"), Some(Encoding::Utf8));
+    // The content form needs the pragma
+    assert_eq!(encoding::prescan(b"<meta content=\"text/html; charset=utf-8\">"), None);
+    assert_eq!(encoding::prescan(b"<meta name=viewport><p>"), None);
+    assert_eq!(encoding::prescan(b"<meta charset=bogus>"), None);
+}
+
+#[test]
+fn skips_comments_and_attributes_in_the_prescan () {
+    assert_eq!(encoding::prescan(b"<!-- <meta charset=utf-8> --><meta charset=latin1>"), Some(Encoding::Windows1252));
+    assert_eq!(encoding::prescan(b"<p title='<meta charset=utf-8>' data-x=\"a > b\"><meta charset=latin1>"), Some(Encoding::Windows1252));
+    assert_eq!(encoding::prescan(b"<!--><meta charset=utf-8>"), Some(Encoding::Utf8));
+    assert_eq!(encoding::prescan(b"<meta charset=utf-8 charset=latin1>"), Some(Encoding::Utf8));
+    // Only the start of the document is looked at
+    let mut late = vec![b' '; 1024];
+    late.extend_from_slice(b"<meta charset=utf-8>");
+    assert_eq!(encoding::sniff(&late, None), (Encoding::Windows1252, 0));
+}
+
+#[test]
+fn reads_utf_16_labels_in_meta_as_utf_8 () {
+    // A document whose meta tag could be read is not in UTF-16
+    assert_eq!(encoding::prescan(b"<meta charset=utf-16>"), Some(Encoding::Utf8));
+    assert_eq!(encoding::prescan(b"<meta http-equiv=content-type content='text/html; charset=UTF-16BE'>"), Some(Encoding::Utf8));
+    // Labels from the transport layer are used as they are
+    assert_eq!(encoding::sniff(b"<\x00", Some("utf-16")), (Encoding::Utf16Le, 0));
+}
+
+#[test]
+fn decodes_windows_1252 () {
+    let bytes: Vec<u8> = (0x80..=0x9f).collect();
+    let text: String = Decoder::new(Encoding::Windows1252).decode(&bytes);
+    assert_eq!(text, "\u{20ac}\u{81}\u{201a}\u{192}\u{201e}\u{2026}\u{2020}\u{2021}\u{2c6}\u{2030}\u{160}\u{2039}\u{152}\u{8d}\u{17d}\u{8f}\
+                      \u{90}\u{2018}\u{2019}\u{201c}\u{201d}\u{2022}\u{2013}\u{2014}\u{2dc}\u{2122}\u{161}\u{203a}\u{153}\u{9d}\u{17e}\u{178}");
+    assert_eq!(encoding::windows_1252(b'a'), 'a');
+    assert_eq!(encoding::windows_1252(0xe9), '\u{e9}');
+    assert_eq!(encoding::windows_1252(0xff), '\u{ff}');
+}
+
+#[test]
+fn decodes_characters_split_between_chunks () {
+    // "a€😀" in UTF-8 is 1 + 3 + 4 bytes
+    let utf8 = "a\u{20ac}\u{1f600}".as_bytes();
+    for split in 0..=utf8.len() {
+        let mut decoder = Decoder::new(Encoding::Utf8);
+        let mut text = decoder.decode(&utf8[..split]);
+        text.push_str(&decoder.decode(&utf8[split..]));
+        text.push_str(&decoder.finish());
+        assert_eq!(text, "a\u{20ac}\u{1f600}", "split at {}", split);
+    }
+
+    let mut decoder = Decoder::new(Encoding::Utf8);
+    assert_eq!(decoder.decode(b"a\xe2\x82"), "a");
+    assert_eq!(decoder.decode(b"\xac\xff"), "\u{20ac}\u{fffd}");
+    assert_eq!(decoder.decode(b"\xf0\x9f"), "");
+    assert_eq!(decoder.finish(), "\u{fffd}");
+
+    // "a😀" in UTF-16 is one code unit and a surrogate pair
+    let utf16le = b"a\x00\x3d\xd8\x00\xde";
+    let utf16be = b"\x00a\xd8\x3d\xde\x00";
+    for &(encoding, bytes) in &[(Encoding::Utf16Le, utf16le), (Encoding::Utf16Be, utf16be)] {
+        for split in 0..=bytes.len() {
+            let mut decoder = Decoder::new(encoding);
+            let mut text = decoder.decode(&bytes[..split]);
+            text.push_str(&decoder.decode(&bytes[split..]));
+            text.push_str(&decoder.finish());
+            assert_eq!(text, "a\u{1f600}", "{:?} split at {}", encoding, split);
+        }
+    }
+
+    // A high surrogate waits for the next chunk, a lone one is replaced
+    let mut decoder = Decoder::new(Encoding::Utf16Le);
+    assert_eq!(decoder.decode(b"a\x00\x3d\xd8"), "a");
+    assert_eq!(decoder.decode(b"b\x00"), "\u{fffd}b");
+    assert_eq!(decoder.decode(b"\x3d"), "");
+    assert_eq!(decoder.finish(), "\u{fffd}");
+}
+
+#[test]
+fn gives_positions_in_the_decoded_source () {
+    // The quotes are one byte each in windows-1252 and three in UTF-8
+    let result = html::parse_bytes(b"\x93\x94<p id=a id=b>x", None);
+    assert_eq!(result.source, "\u{201c}\u{201d}<p id=a id=b>x");
+    let p = result.document.get_element_by_id("a").unwrap();
+    let span = result.document[p].span.unwrap();
+    assert_eq!(&result.source[span.start..span.end], "<p id=a id=b>x");
+    assert_eq!((span.line, span.column), (1, 3));
+    let duplicate = result.errors.iter()
+        .find(|error| error.kind == html::ParseErrorKind::DuplicateAttribute("id".to_string()))
+        .unwrap();
+    assert!(result.source[..duplicate.offset].ends_with("id=b"));
+
+    // Byte order marks are not part of the source
+    let result = html::parse_bytes(b"\xff\xfe<\x00b\x00>\x00\xe9\x00", None);
+    assert_eq!(result.source, "<b>\u{e9}");
+    let b = result.document.query_selector(result.document.root(), "b").unwrap().unwrap();
+    assert_eq!(result.document[b].span.map(|span| (span.start, span.end)), Some((0, 5)));
+}
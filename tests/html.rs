@@ -0,0 +1,192 @@
+extern crate rbe;
+
+use std::thread;
+use rbe::dom::{ Document, NodeType, Span };
+use rbe::html::{ self, ParseError, ParseErrorKind, Parser };
+use rbe::serializer;
+
+/// The markup of the body of a parsed document
+fn body_html (source: &str) -> String {
+    let document = html::parse(source.to_string()).document;
+    document.inner_html(document.body().unwrap())
+}
+
+#[test]
+fn handles_deeply_nested_elements () {
+    // Nothing walks the tree with recursion, so the depth is not limited by the call stack
+    let depth = 100_000;
+    let source = format!("{}x{}", "<span>".repeat(depth), "</span>".repeat(depth));
+    let document = html::parse(source.clone()).document;
+    let body = document.body().unwrap();
+    assert_eq!(document.descendants(body).count(), depth + 1);
+    assert_eq!(document.inner_html(body), source);
+    assert_eq!(serializer::serialize_document(&document), format!("<html><head></head><body>{}</body></html>", source));
+
+    let formatting = html::parse(format!("{}x", "<b>".repeat(20_000))).document;
+    assert_eq!(formatting.text_content(formatting.root()), "x");
+
+    // The html5lib format indents each line by its depth, so it's only tried on a smaller
+    // tree, but with a small stack
+    let dump = thread::Builder::new().stack_size(32 * 1024).spawn(|| {
+        let document = html::parse("<span>".repeat(1000)).document;
+        serializer::dump_tree(&document, document.root())
+    }).unwrap().join().unwrap();
+    assert_eq!(dump.lines().count(), 1003);
+}
+
+#[test]
+fn gives_text_the_span_of_its_source () {
+    let result = html::parse("&amp x".to_string());
+    let document = result.document;
+    let body = document.body().unwrap();
+    let text = document[body].first_child().unwrap();
+    assert_eq!(document[text].span, Some(Span { start: 0, end: 6, line: 1, column: 1 }));
+    assert_eq!(result.errors, vec![
+        ParseError { kind: ParseErrorKind::MissingSemicolonAfterCharacterReference, offset: 0, line: 1, column: 1 },
+        ParseError { kind: ParseErrorKind::MissingDoctype, offset: 0, line: 1, column: 1 }
+    ]);
+
+    // Text moved out of a table keeps its own span instead of the one of the tag after it
+    let result = html::parse("<!DOCTYPE html><table>\n x<tr>".to_string());
+    let document = result.document;
+    let body = document.body().unwrap();
+    let text = document[body].first_child().unwrap();
+    assert_eq!(document.text_content(text), "\n x");
+    assert_eq!(document[text].span, Some(Span { start: 22, end: 25, line: 1, column: 23 }));
+    assert_eq!(result.errors[0], ParseError { kind: ParseErrorKind::UnexpectedText, offset: 22, line: 1, column: 23 });
+}
+
+#[test]
+fn returns_the_errors_of_fragments () {
+    let mut document = Document::new();
+    let (nodes, errors) = html::parse_fragment(&mut document, "<b>x</i>\n&amp", "div");
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(document.text_content(nodes[0]), "x\n&");
+    assert_eq!(errors, vec![
+        ParseError { kind: ParseErrorKind::UnexpectedClosingTag("i".to_string()), offset: 4, line: 1, column: 5 },
+        ParseError { kind: ParseErrorKind::MissingSemicolonAfterCharacterReference, offset: 9, line: 2, column: 1 },
+        ParseError { kind: ParseErrorKind::UnclosedElement("b".to_string()), offset: 13, line: 2, column: 5 }
+    ]);
+}
+
+#[test]
+fn reports_errors_at_their_position () {
+    // Offsets count bytes, columns count characters
+    let source = "<!DOCTYPE html>\n<p id=a id=b>caf\u{e9} \0</p>\n<\u{fc}>stra\u{df}e</i><div></p  x=1>\n<b><i>x</b></i><a href=\"x";
+    let errors = html::parse(source.to_string()).errors;
+    assert_eq!(errors, vec![
+        ParseError { kind: ParseErrorKind::DuplicateAttribute("id".to_string()), offset: 28, line: 2, column: 13 },
+        ParseError { kind: ParseErrorKind::UnexpectedNullCharacter, offset: 35, line: 2, column: 19 },
+        ParseError { kind: ParseErrorKind::MissingTagName, offset: 42, line: 3, column: 2 },
+        ParseError { kind: ParseErrorKind::UnexpectedClosingTag("i".to_string()), offset: 52, line: 3, column: 10 },
+        ParseError { kind: ParseErrorKind::UnexpectedClosingTag("p".to_string()), offset: 61, line: 3, column: 19 },
+        ParseError { kind: ParseErrorKind::EndTagWithAttributes, offset: 69, line: 3, column: 27 },
+        ParseError { kind: ParseErrorKind::MisnestedTag("b".to_string()), offset: 78, line: 4, column: 8 },
+        ParseError { kind: ParseErrorKind::MisnestedTag("i".to_string()), offset: 82, line: 4, column: 12 },
+        ParseError { kind: ParseErrorKind::UnexpectedEof, offset: 96, line: 4, column: 26 },
+        ParseError { kind: ParseErrorKind::UnclosedElement("div".to_string()), offset: 96, line: 4, column: 26 }
+    ]);
+}
+
+#[test]
+fn parses_chunks_like_the_whole_input () {
+    let source = "<!DOCTYPE html><title>caf\u{e9} &amp; \u{20ac}</title><p class=\"\u{e9}t\u{e9}\">na\u{ef}ve &eacute;\r\n<b>\u{1f600}<i>x</b> y<table>\u{e9}<tr><td>1</table><!-- \u{fc} -->";
+    let whole = html::parse(source.to_string());
+    for &size in &[1, 2, 3, 5, 8] {
+        let mut parser = Parser::new();
+        for chunk in source.as_bytes().chunks(size) {
+            parser.feed(chunk);
+        }
+        let chunked = parser.finish();
+        let document = &chunked.document;
+        assert_eq!(serializer::dump_tree(document, document.root()), serializer::dump_tree(&whole.document, whole.document.root()));
+        assert_eq!(chunked.errors, whole.errors);
+        let spans = |document: &Document| document.descendants(document.root()).map(|node| document[node].span).collect::<Vec<_>>();
+        assert_eq!(spans(document), spans(&whole.document));
+    }
+
+    // The tree can be looked at between chunks. Text at the end of a chunk is only added
+    // once it is known where it ends, so a character split by a chunk is never cut in half.
+    let mut parser = Parser::new();
+    parser.feed(b"<p>caf\xc3");
+    let p = parser.document().query_selector(parser.document().root(), "p").unwrap().unwrap();
+    assert_eq!(parser.document().text_content(p), "");
+    parser.feed(b"\xa9<b>");
+    assert_eq!(parser.document().text_content(p), "caf\u{e9}");
+    parser.feed(b"!");
+    assert_eq!(parser.finish().document.text_content(p), "caf\u{e9}!");
+}
+
+#[test]
+fn parses_comments_and_doctypes () {
+    let result = html::parse("<!DOCTYPE html><!-- a --><p>x<!--b--></p><?pi x?>".to_string());
+    let document = result.document;
+    assert_eq!(document.doctype().map(|doctype| &*doctype.name), Some("html"));
+    assert_eq!(serializer::serialize_document(&document),
+               "<!DOCTYPE html><!-- a --><html><head></head><body><p>x<!--b--></p><!--?pi x?--></body></html>");
+    assert_eq!(body_html("<p>x<!--b--></p>"), "<p>x<!--b--></p>");
+    // Processing instructions are comments in html
+    assert_eq!(result.errors.iter().map(|error| &error.kind).collect::<Vec<_>>(), vec![&ParseErrorKind::BogusComment]);
+}
+
+#[test]
+fn parses_void_and_self_closing_elements () {
+    assert_eq!(body_html("<p>a<br>b<img src=x/><br/>c<input type=checkbox></p><hr>"),
+               "<p>a<br>b<img src=\"x/\"><br>c<input type=\"checkbox\"></p><hr>");
+    // A void element never has children, the text after it is its sibling
+    let document = html::parse("<meta charset=utf-8><br>x".to_string()).document;
+    let br = document.query_selector(document.root(), "br").unwrap().unwrap();
+    assert_eq!(document[br].first_child(), None);
+    assert_eq!(document.text_content(document.body().unwrap()), "x");
+}
+
+#[test]
+fn decodes_character_references () {
+    assert_eq!(body_html("<p>&lt;&nbsp;&#169;&#x1F600;&copy &notit; &#0;</p>"),
+               "<p>&lt;&nbsp;\u{a9}\u{1f600}\u{a9} \u{ac}it; \u{fffd}</p>");
+    // Attributes only take references without a semicolon when no name character follows
+    let document = html::parse("<p title=\"a&amp;b&notit;&amp=\">".to_string()).document;
+    let p = document.query_selector(document.root(), "p").unwrap().unwrap();
+    match document[p].node_type {
+        NodeType::Element(ref element) => assert_eq!(element.get_attribute("title"), Some("a&b&notit;&amp=")),
+        _ => unreachable!()
+    }
+}
+
+#[test]
+fn corrects_malformed_markup_like_browsers () {
+    let result = html::parse("<p>one<p>two<ul><li>a<li>b</div></ul><b>1<i>2</b>3</i>".to_string());
+    let document = result.document;
+    assert_eq!(document.inner_html(document.body().unwrap()),
+               "<p>one</p><p>two</p><ul><li>a</li><li>b</li></ul><b>1<i>2</i></b><i>3</i>");
+    assert_eq!(result.errors.iter().map(|error| &error.kind).collect::<Vec<_>>(), vec![
+        &ParseErrorKind::MissingDoctype,
+        &ParseErrorKind::UnexpectedClosingTag("div".to_string()),
+        &ParseErrorKind::MisnestedTag("b".to_string())
+    ]);
+    // Text alone still gets an html, head and body
+    assert_eq!(serializer::serialize_document(&html::parse("x".to_string()).document),
+               "<html><head></head><body>x</body></html>");
+}
+
+#[test]
+fn keeps_raw_text_and_rcdata_as_text () {
+    let document = html::parse("<title>&amp;<i></title><style>a > b {}</style><script>if (a < b) {}</script><textarea>&lt;b></textarea>".to_string()).document;
+    let text = |name| document.text_content(document.query_selector(document.root(), name).unwrap().unwrap());
+    // Character references are only decoded in rcdata
+    assert_eq!(text("title"), "&<i>");
+    assert_eq!(text("textarea"), "<b>");
+    assert_eq!(text("style"), "a > b {}");
+    assert_eq!(text("script"), "if (a < b) {}");
+    assert_eq!(document.query_selector(document.root(), "i, b"), Ok(None));
+}
+
+#[test]
+fn parses_every_kind_of_attribute () {
+    let result = html::parse("<DIV DATA-id=\"1\" aria-label disabled width=100 xml:lang=en id=a ID=b></div>".to_string());
+    let document = result.document;
+    // Names are lowercased, and the first of two attributes with the same name wins
+    assert_eq!(document.inner_html(document.body().unwrap()),
+               "<div data-id=\"1\" aria-label=\"\" disabled=\"\" width=\"100\" xml:lang=\"en\" id=\"a\"></div>");
+    assert!(result.errors.iter().any(|error| error.kind == ParseErrorKind::DuplicateAttribute("id".to_string())));
+}
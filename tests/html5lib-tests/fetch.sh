@@ -0,0 +1,29 @@
+#!/bin/sh
+# Vendor the html5lib-tests files of one upstream commit and update the failure baselines.
+# Usage: tests/html5lib-tests/fetch.sh <revision>
+# HTML5LIB_TESTS_URL clones from a mirror instead of GitHub.
+set -e
+
+if [ $# -ne 1 ]; then
+    echo "usage: $0 <revision>" >&2
+    exit 1
+fi
+
+directory=$(cd "$(dirname "$0")" && pwd)
+checkout=$(mktemp -d)
+trap 'rm -rf "$checkout"' EXIT
+
+git clone --quiet "${HTML5LIB_TESTS_URL:-https://github.com/html5lib/html5lib-tests}" "$checkout"
+git -C "$checkout" checkout --quiet "$1"
+
+# Our own files are named rbe.*, everything else comes from upstream
+find "$directory/tokenizer" -name '*.test' ! -name 'rbe.test' -delete
+find "$directory/tree-construction" -name '*.dat' ! -name 'rbe.dat' -delete
+cp "$checkout"/tokenizer/*.test "$directory/tokenizer/"
+# Every top level file, which includes the fragment and template tests. The ones in
+# `scripted/` need scripting.
+cp "$checkout"/tree-construction/*.dat "$directory/tree-construction/"
+git -C "$checkout" rev-parse HEAD > "$directory/REVISION"
+
+cd "$directory/../.."
+HTML5LIB_BLESS=1 cargo test --test html5lib_tokenizer --test html5lib_tree_construction
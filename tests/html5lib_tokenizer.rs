@@ -0,0 +1,188 @@
+//! Runs the tokenizer against test files in the html5lib-tests format, see
+//! https://github.com/html5lib/html5lib-tests/tree/master/tokenizer
+//!
+//! Every `.test` file in `tests/html5lib-tests/tokenizer` is run, see the README there for
+//! vendoring the upstream files. Only the tokens are compared, not the parse errors. Failures
+//! are checked against `tests/html5lib-tests/tokenizer-failures.txt`.
+
+extern crate rbe;
+extern crate rustc_serialize;
+
+mod conformance;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use rustc_serialize::json::Json;
+use rbe::tokenizer::{ Tokenizer, Token, ContentModel };
+use conformance::Results;
+
+/// Turn a token into the array form the tests use
+fn to_json (token: Token) -> Json {
+    let string = |s: String| Json::String(s);
+    let optional = |s: Option<String>| s.map_or(Json::Null, Json::String);
+    match token {
+        Token::Doctype { name, public_id, system_id, force_quirks } => Json::Array(vec![
+            string("DOCTYPE".to_string()), optional(name), optional(public_id), optional(system_id),
+            Json::Boolean(!force_quirks)
+        ]),
+        Token::StartTag { name, attributes, self_closing } => {
+            let attributes = attributes.into_iter().map(|(name, value)| (name.to_string(), Json::String(value))).collect();
+            let mut json = vec![string("StartTag".to_string()), string(name.to_string()), Json::Object(attributes)];
+            if self_closing {
+                json.push(Json::Boolean(true));
+            }
+            Json::Array(json)
+        },
+        Token::EndTag { name } => Json::Array(vec![string("EndTag".to_string()), string(name.to_string())]),
+        Token::Text(text) => Json::Array(vec![string("Character".to_string()), string(text.into_owned())]),
+        Token::Comment(data) => Json::Array(vec![string("Comment".to_string()), string(data)]),
+        Token::Eof => Json::Null
+    }
+}
+
+/// Merge adjacent character tokens and drop `false` self-closing flags, so that equal token
+/// lists compare equal
+fn normalize (tokens: Vec<Json>) -> Vec<Json> {
+    let mut result: Vec<Json> = Vec::new();
+    for token in tokens {
+        let mut token = match token {
+            Json::Array(token) => token,
+            other => panic!("unexpected token {}", other)
+        };
+        if token[0].as_string() == Some("StartTag") && token.len() == 4 && token[3] == Json::Boolean(false) {
+            token.pop();
+        }
+        if token[0].as_string() == Some("Character") {
+            if let Some(&mut Json::Array(ref mut previous)) = result.last_mut() {
+                if previous[0].as_string() == Some("Character") {
+                    let text = format!("{}{}", previous[1].as_string().unwrap(), token[1].as_string().unwrap());
+                    previous[1] = Json::String(text);
+                    continue;
+                }
+            }
+        }
+        result.push(Json::Array(token));
+    }
+    result
+}
+
+/// Resolve the `\uXXXX` escapes of tests marked with `doubleEscaped`. Returns `None` for lone
+/// surrogates, which can't be part of a Rust string.
+fn unescape (s: &str) -> Option<String> {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(index) = rest.find("\\u") {
+        result.push_str(&rest[..index]);
+        let code = u32::from_str_radix(rest.get(index + 2..index + 6)?, 16).ok()?;
+        result.push(std::char::from_u32(code)?);
+        rest = &rest[index + 6..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+fn unescape_json (json: &Json) -> Option<Json> {
+    Some(match *json {
+        Json::String(ref s) => Json::String(unescape(s)?),
+        Json::Array(ref items) => Json::Array(items.iter().map(unescape_json).collect::<Option<_>>()?),
+        Json::Object(ref map) => {
+            let mut result = BTreeMap::new();
+            for (key, value) in map {
+                result.insert(unescape(key)?, unescape_json(value)?);
+            }
+            Json::Object(result)
+        },
+        ref other => other.clone()
+    })
+}
+
+fn content_model (state: &str) -> Option<ContentModel> {
+    match state {
+        "Data state" => Some(ContentModel::Data),
+        "PLAINTEXT state" => Some(ContentModel::PlainText),
+        "RCDATA state" => Some(ContentModel::RcData),
+        "RAWTEXT state" => Some(ContentModel::RawText),
+        "Script data state" => Some(ContentModel::ScriptData),
+        _ => None
+    }
+}
+
+fn tokenize (input: &str, content_model: ContentModel, last_start_tag: Option<&str>) -> Vec<Json> {
+    let mut tokenizer = Tokenizer::from_source(input);
+    tokenizer.set_content_model(content_model);
+    if let Some(tag) = last_start_tag {
+        tokenizer.set_last_start_tag(tag);
+    }
+    let mut tokens = Vec::new();
+    while let Some((token, _)) = tokenizer.next_token() {
+        if token == Token::Eof {
+            break;
+        }
+        tokens.push(to_json(token));
+    }
+    tokens
+}
+
+/// Run a test in each of its initial states. `id` names the test in the baseline.
+fn run_test (id: &str, test: &Json, results: &mut Results) {
+    let description = test["description"].as_string().unwrap_or("").to_string();
+    let double_escaped = test.find("doubleEscaped").and_then(Json::as_boolean) == Some(true);
+    let (input, output) = if double_escaped {
+        match (unescape(test["input"].as_string().unwrap()), unescape_json(&test["output"])) {
+            (Some(input), Some(output)) => (input, output),
+            _ => {
+                results.skipped += 1;
+                return;
+            }
+        }
+    } else {
+        (test["input"].as_string().unwrap().to_string(), test["output"].clone())
+    };
+    let expected = normalize(output.as_array().unwrap().clone());
+    let last_start_tag = test.find("lastStartTag").and_then(Json::as_string);
+
+    let states = match test.find("initialStates").and_then(Json::as_array) {
+        Some(states) => states.iter().map(|state| state.as_string().unwrap().to_string()).collect(),
+        None => vec!["Data state".to_string()]
+    };
+    for state in states {
+        let content_model = match content_model(&state) {
+            Some(content_model) => content_model,
+            None => {
+                results.skipped += 1;
+                continue;
+            }
+        };
+        let actual = normalize(tokenize(&input, content_model, last_start_tag));
+        if actual == expected {
+            results.passed += 1;
+        } else {
+            results.fail(format!("{} ({})", id, state), format!("{}\n  input:    {:?}\n  expected: {}\n  actual:   {}",
+                description, input, Json::Array(expected.clone()), Json::Array(actual)));
+        }
+    }
+}
+
+#[test]
+fn html5lib_tokenizer () {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/html5lib-tests");
+    let mut paths: Vec<_> = fs::read_dir(directory.join("tokenizer")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "test"))
+        .collect();
+    paths.sort();
+
+    let mut results = Results::default();
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let json = Json::from_str(&source).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        let tests = json.find("tests").and_then(Json::as_array).cloned().unwrap_or_default();
+        let file = path.file_name().unwrap().to_string_lossy();
+        for (index, test) in tests.iter().enumerate() {
+            run_test(&format!("{}:{}", file, index + 1), test, &mut results);
+        }
+    }
+
+    conformance::check("html5lib tokenizer tests", &results, &directory.join("tokenizer-failures.txt"));
+}
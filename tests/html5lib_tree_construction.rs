@@ -0,0 +1,124 @@
+//! Runs the tree builder against test files in the html5lib-tests format, see
+//! https://github.com/html5lib/html5lib-tests/tree/master/tree-construction
+//!
+//! Every `.dat` file in `tests/html5lib-tests/tree-construction` is run, see the README there
+//! for vendoring the upstream files. Only the trees are compared, not the parse errors. Tests
+//! that need scripting, or a fragment context in the svg or mathml namespace, are skipped.
+//! Failures are checked against `tests/html5lib-tests/tree-construction-failures.txt`.
+
+extern crate rbe;
+
+mod conformance;
+
+use std::fs;
+use std::path::Path;
+use rbe::{ html, serializer };
+use rbe::dom::Document;
+use conformance::Results;
+
+#[derive(Default)]
+struct Test {
+    data: String,
+    fragment_context: Option<String>,
+    scripting: Option<bool>,
+    document: String
+}
+
+/// Split a `.dat` file into its tests. Each test is a list of sections that start with a
+/// `#name` line, beginning with `#data`.
+fn parse_tests (source: &str) -> Vec<Test> {
+    let mut tests = Vec::new();
+    let mut sections: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in source.lines() {
+        match line {
+            "#data" | "#errors" | "#new-errors" | "#document-fragment" | "#document" | "#script-on" | "#script-off" => {
+                if line == "#data" && !sections.is_empty() {
+                    tests.push(to_test(&sections));
+                    sections.clear();
+                }
+                sections.push((line, Vec::new()));
+            },
+            _ => {
+                if let Some(&mut (_, ref mut lines)) = sections.last_mut() {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    if !sections.is_empty() {
+        tests.push(to_test(&sections));
+    }
+    tests
+}
+
+fn to_test (sections: &[(&str, Vec<&str>)]) -> Test {
+    let mut test = Test::default();
+    for &(name, ref lines) in sections {
+        match name {
+            "#data" => test.data = lines.join("\n"),
+            "#document-fragment" => test.fragment_context = lines.first().map(|line| line.to_string()),
+            "#script-on" => test.scripting = Some(true),
+            "#script-off" => test.scripting = Some(false),
+            "#document" => {
+                // Tests are separated by an empty line
+                let mut lines = &lines[..];
+                while lines.last() == Some(&"") {
+                    lines = &lines[..lines.len() - 1];
+                }
+                test.document = lines.join("\n");
+            },
+            _ => {}
+        }
+    }
+    test
+}
+
+/// Run a test, `id` names it in the baseline
+fn run_test (id: String, test: &Test, results: &mut Results) {
+    if test.scripting == Some(true) {
+        results.skipped += 1;
+        return;
+    }
+    let actual = match test.fragment_context {
+        Some(ref context) if context.contains(' ') => {
+            results.skipped += 1;
+            return;
+        },
+        Some(ref context) => {
+            let mut document = Document::new();
+            let (nodes, _) = html::parse_fragment(&mut document, &test.data, context);
+            let fragment = document.fragment(nodes);
+            serializer::dump_tree(&document, fragment)
+        },
+        None => {
+            let document = html::parse(test.data.clone()).document;
+            serializer::dump_tree(&document, document.root())
+        }
+    };
+    if actual == test.document {
+        results.passed += 1;
+    } else {
+        results.fail(id, format!("{:?}\n  expected:\n{}\n  actual:\n{}", test.data, test.document, actual));
+    }
+}
+
+#[test]
+fn html5lib_tree_construction () {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/html5lib-tests");
+    let mut paths: Vec<_> = fs::read_dir(directory.join("tree-construction")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "dat"))
+        .collect();
+    paths.sort();
+
+    let mut results = Results::default();
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let file = path.file_name().unwrap().to_string_lossy();
+        for (index, test) in parse_tests(&source).iter().enumerate() {
+            run_test(format!("{}:{}", file, index + 1), test, &mut results);
+        }
+    }
+
+    conformance::check("html5lib tree construction tests", &results, &directory.join("tree-construction-failures.txt"));
+}
@@ -0,0 +1,49 @@
+extern crate rbe;
+
+use rbe::{ html, layout, parser, style };
+use rbe::layout::{ Dimensions, LayoutBox };
+
+const CSS: &str = "html, body, div, p, pre { display: block; } pre { white-space: pre; } .lines { white-space: pre-line; }";
+
+/// The rendered text of every text box in the body, in document order
+fn texts (source: &str) -> Vec<String> {
+    let document = html::parse(source.to_string()).document;
+    let stylesheet = parser::parse_stylesheet(CSS);
+    let styled = style::style_tree(&document, document.document_element().unwrap(), &stylesheet);
+    let root = layout::layout_tree(&styled, Dimensions::default());
+
+    let mut result = Vec::new();
+    let mut stack: Vec<&LayoutBox> = vec![&root];
+    while let Some(layout_box) = stack.pop() {
+        result.extend(layout_box.text.clone());
+        stack.extend(layout_box.children.iter().rev());
+    }
+    result
+}
+
+#[test]
+fn keeps_whitespace_text_in_the_dom () {
+    let document = html::parse("<p><b>a</b> <i>b</i></p><pre>\n  x\n  y </pre>".to_string()).document;
+    let body = document.body().unwrap();
+    assert_eq!(document.text_content(body), "a b  x\n  y ");
+}
+
+#[test]
+fn collapses_whitespace_across_inline_boxes () {
+    assert_eq!(texts("<p><b>a </b> <i> b</i></p>"), vec!["a ", "", "b"]);
+    assert_eq!(texts("<p>  a \t\n b  </p>"), vec!["a b"]);
+    assert_eq!(texts("<p>a<b> </b><i> </i>b</p>"), vec!["a", " ", "", "b"]);
+}
+
+#[test]
+fn removes_whitespace_at_block_edges () {
+    assert_eq!(texts("<div> <b> x </b> </div>"), vec!["x"]);
+    // A block splits the inline content around it into separate lines
+    assert_eq!(texts("<div>a <p> b </p> c</div>"), vec!["a", "b", "c"]);
+}
+
+#[test]
+fn keeps_whitespace_for_pre () {
+    assert_eq!(texts("<p>a <pre>  x\n  y </pre></p>"), vec!["a", "  x\n  y "]);
+    assert_eq!(texts("<p class=lines>a  \n  b <b>c </b>\n d</p>"), vec!["a\nb ", "c", "\nd"]);
+}
@@ -0,0 +1,71 @@
+extern crate rbe;
+
+use rbe::html;
+use rbe::serializer;
+
+/// Parse a document and return the markup of its `body`
+fn body_html (source: &str) -> String {
+    let document = html::parse(source.to_string()).document;
+    let root = document.document_element().unwrap();
+    let body = document.children(root).nth(1).unwrap();
+    document.inner_html(body)
+}
+
+#[test]
+fn serializes_elements_and_attributes () {
+    assert_eq!(body_html("<p id=a class='x y'>Hi <b>there</b></p>"),
+               "<p id=\"a\" class=\"x y\">Hi <b>there</b></p>");
+    assert_eq!(body_html("<img src=a.png><br/>x<hr>"), "<img src=\"a.png\"><br>x<hr>");
+    assert_eq!(body_html("<p>x<!-- note --></p>"), "<p>x<!-- note --></p>");
+}
+
+#[test]
+fn escapes_text_and_attributes () {
+    assert_eq!(body_html("<p title='a \"b\" &amp; <c>'>1 &lt; 2 &amp;&amp; 3 &gt; 2&nbsp;</p>"),
+               "<p title=\"a &quot;b&quot; &amp; <c>\">1 &lt; 2 &amp;&amp; 3 &gt; 2&nbsp;</p>");
+}
+
+#[test]
+fn does_not_escape_raw_text () {
+    assert_eq!(body_html("<p><script>if (a < b && c) {}</script><style>a > b {}</style></p>"),
+               "<p><script>if (a < b && c) {}</script><style>a > b {}</style></p>");
+    assert_eq!(body_html("<textarea>a < b</textarea>"), "<textarea>a &lt; b</textarea>");
+}
+
+#[test]
+fn serializes_foreign_content () {
+    assert_eq!(body_html("<svg viewbox='0 0 1 1'><lineargradient/><![CDATA[a<b]]></svg><math><mi>x</math>"),
+               "<svg viewBox=\"0 0 1 1\"><linearGradient></linearGradient>a&lt;b</svg><math><mi>x</mi></math>");
+    assert_eq!(body_html("<svg><g><p>x</svg>"), "<svg><g></g></svg><p>x</p>");
+}
+
+#[test]
+fn serializes_documents () {
+    let result = html::parse("<!DOCTYPE html><title>T</title><p>x".to_string());
+    let document = &result.document;
+    assert_eq!(serializer::serialize_document(document),
+               "<!DOCTYPE html><html><head><title>T</title></head><body><p>x</p></body></html>");
+    let head = document[document.document_element().unwrap()].first_child().unwrap();
+    assert_eq!(document.outer_html(head), "<head><title>T</title></head>");
+}
+
+#[test]
+fn round_trips_through_the_parser () {
+    let documents = [
+        "<!DOCTYPE html><html><head><title>A &amp; B</title><style>p > a {}</style></head><body><p class=a>One<p>Two</body></html>",
+        "<table><tr><td>1<td>2</table><ul><li>a<li>b</ul>",
+        "<b>1<p>2</b>3</p>",
+        "<a href='?a=1&amp;b=2' title='&quot;'>link</a><script>x = '</p>' < 1</script>",
+        "<select><option>a<option selected>b</select><textarea>&lt;b&gt;</textarea>",
+        "<p>caf\u{e9} &nbsp; \u{1f600}<!-- c --><br><input value=\"x\">",
+        "<svg><clipPath/><foreignObject><p>a</p></foreignObject></svg><math><mtext>b</mtext></math>",
+        "<template><tr><td>1</template><table><template><col></template></table>",
+    ];
+    for source in documents.iter() {
+        let first = html::parse(source.to_string()).document;
+        let first = first.outer_html(first.root());
+        let second = html::parse(first.clone()).document;
+        let second = second.outer_html(second.root());
+        assert_eq!(first, second, "{}", source);
+    }
+}
@@ -0,0 +1,41 @@
+extern crate rbe;
+
+use rbe::{ html, parser, style };
+use rbe::css::Value;
+use rbe::dom::Document;
+
+/// The `display` values set on the elements under the root, in document order
+fn displays (document: &Document, css: &str) -> Vec<String> {
+    let stylesheet = parser::parse_stylesheet(css);
+    let root = document.document_element().unwrap();
+    let styled = style::style_tree(document, root, &stylesheet);
+
+    let mut result = Vec::new();
+    let mut stack: Vec<&style::StyledNode> = styled.children.iter().rev().collect();
+    while let Some(node) = stack.pop() {
+        if let Some(Value::Keyword(display)) = node.specified_values.get("display") {
+            result.push(display.clone());
+        }
+        stack.extend(node.children.iter().rev());
+    }
+    result
+}
+
+#[test]
+fn matches_type_selectors_in_namespaces () {
+    let document = html::parse("<A></A><svg><a></a><linearGradient/></svg><math><a></a></math>".to_string()).document;
+
+    // Html names match in any case, foreign ones only exactly
+    assert_eq!(displays(&document, "A { display: a; } lineargradient { display: x; } linearGradient { display: g; }"),
+               vec!["a", "g"]);
+
+    let namespaces = "@namespace svg url(http://www.w3.org/2000/svg); @namespace m \"http://www.w3.org/1998/Math/MathML\";";
+    assert_eq!(displays(&document, &format!("{} *|a {{ display: any; }} svg|a {{ display: s; }} m|a {{ display: m; }}", namespaces)),
+               vec!["any", "s", "m"]);
+    assert_eq!(displays(&document, &format!("{} m|* {{ display: m; }} |a {{ display: none; }}", namespaces)),
+               vec!["m", "m"]);
+
+    // The default namespace applies to selectors without a prefix
+    assert_eq!(displays(&document, "@namespace url(http://www.w3.org/1999/xhtml); a { display: h; } * { display: all; }"),
+               vec!["all", "all", "h"]);
+}
@@ -0,0 +1,62 @@
+extern crate rbe;
+
+use std::borrow::Cow;
+use rbe::atom::Atom;
+use rbe::dom::Span;
+use rbe::html::{ ParseError, ParseErrorKind };
+use rbe::tokenizer::{ Tokenizer, Token };
+
+#[test]
+fn borrows_text_written_as_is () {
+    let mut tokenizer = Tokenizer::from_source("plain\ntext<p>a &amp; b</p>");
+    match tokenizer.next_token() {
+        Some((Token::Text(Cow::Borrowed(text)), _)) => assert_eq!(text, "plain\ntext"),
+        token => panic!("expected borrowed text, got {:?}", token)
+    }
+    tokenizer.next_token();
+    match tokenizer.next_token() {
+        Some((Token::Text(Cow::Owned(text)), _)) => assert_eq!(text, "a & b"),
+        token => panic!("expected decoded text, got {:?}", token)
+    }
+
+    let mut tokenizer = Tokenizer::from_source("a\r\nb");
+    assert_eq!(tokenizer.next_token().map(|(token, _)| token), Some(Token::Text(Cow::Borrowed("a\nb"))));
+}
+
+#[test]
+fn names_tags_with_atoms () {
+    let mut tokenizer = Tokenizer::from_source("<DIV><my-Element></my-element>");
+    let names: Vec<Atom> = (0..3).filter_map(|_| match tokenizer.next_token() {
+        Some((Token::StartTag { name, .. }, _)) | Some((Token::EndTag { name }, _)) => Some(name),
+        _ => None
+    }).collect();
+    assert_eq!(names, vec![Atom::from("div"), Atom::from("my-element"), Atom::from("my-element")]);
+    assert_eq!(names[1], Atom::from(String::from("my-element")));
+    assert!(names[0] != names[1]);
+    assert_eq!(&*names[1], "my-element");
+}
+
+#[test]
+fn starts_text_at_character_references () {
+    let mut tokenizer = Tokenizer::from_source("&amp x");
+    assert_eq!(tokenizer.next_token(), Some((Token::Text(Cow::Borrowed("& x")), Span { start: 0, end: 6, line: 1, column: 1 })));
+    assert_eq!(tokenizer.errors, vec![
+        ParseError { kind: ParseErrorKind::MissingSemicolonAfterCharacterReference, offset: 0, line: 1, column: 1 }
+    ]);
+
+    // Offsets count bytes, columns count characters
+    let mut tokenizer = Tokenizer::from_source("<p>\n\u{e9}&#x41 b&bogus;");
+    tokenizer.next_token();
+    assert_eq!(tokenizer.next_token(), Some((Token::Text(Cow::Borrowed("\n\u{e9}A b&bogus;")), Span { start: 3, end: 20, line: 1, column: 4 })));
+    assert_eq!(tokenizer.errors, vec![
+        ParseError { kind: ParseErrorKind::MissingSemicolonAfterCharacterReference, offset: 6, line: 2, column: 2 },
+        ParseError { kind: ParseErrorKind::UnknownNamedCharacterReference, offset: 13, line: 2, column: 9 }
+    ]);
+
+    let mut tokenizer = Tokenizer::from_source("<p>&#;");
+    tokenizer.next_token();
+    assert_eq!(tokenizer.next_token(), Some((Token::Text(Cow::Borrowed("&#;")), Span { start: 3, end: 6, line: 1, column: 4 })));
+    assert_eq!(tokenizer.errors, vec![
+        ParseError { kind: ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference, offset: 3, line: 1, column: 4 }
+    ]);
+}